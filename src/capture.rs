@@ -0,0 +1,164 @@
+use std::{
+    fs::File,
+    io::{LineWriter, Write},
+    sync::{Mutex, OnceLock},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::Result;
+use hyper::{StatusCode, Uri};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::settings::Settings;
+
+/// A recorded entry along with the wall-clock time it was recorded at, in
+/// milliseconds since the Unix epoch. `t` is used when replaying, `timestamp`
+/// is for matching entries up with logs.
+#[derive(Serialize, Debug)]
+struct TimestampedEntry<'a> {
+    timestamp: u128,
+
+    #[serde(flatten)]
+    entry: &'a CaptureEntry<'a>,
+}
+
+/// A single recorded exchange with the Hue bridge. The format matches the
+/// fixtures replayed by `tests/replay.rs`.
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum CaptureEntry<'a> {
+    /// Raw `data` of an eventsource event
+    Sse { t: u128, data: &'a str },
+
+    Get {
+        t: u128,
+        path: &'a str,
+        status: u16,
+        body: Value,
+    },
+
+    Put {
+        t: u128,
+        path: &'a str,
+        request: Value,
+        status: u16,
+        response: Value,
+    },
+}
+
+/// Records traffic with the Hue bridge to a JSON lines file, for building
+/// test fixtures and debugging
+struct Capture {
+    file: Mutex<LineWriter<std::fs::File>>,
+    appkey: String,
+    start: Instant,
+}
+
+static CAPTURE: OnceLock<Capture> = OnceLock::new();
+
+/// Bodies are stored as JSON when possible, so that they remain readable
+fn body_to_value(body: &[u8]) -> Value {
+    serde_json::from_slice(body)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(body).into_owned()))
+}
+
+fn path_of(uri: &Uri) -> &str {
+    uri.path_and_query()
+        .map(|path_and_query| path_and_query.as_str())
+        .unwrap_or_else(|| uri.path())
+}
+
+/// Starts recording Hue bridge traffic, if `hue_bridge.capture_file` is set.
+/// An existing file is overwritten, as `t` restarts from zero on every run.
+pub fn start_capture(settings: &Settings) -> Result<()> {
+    let Some(capture_file) = &settings.hue_bridge.capture_file else {
+        return Ok(());
+    };
+
+    let file = File::create(capture_file)?;
+
+    let capture = Capture {
+        file: Mutex::new(LineWriter::new(file)),
+        appkey: settings.hue_bridge.appkey.clone(),
+        start: Instant::now(),
+    };
+
+    if CAPTURE.set(capture).is_err() {
+        return Err(eyre!("Capture has already been started"));
+    }
+
+    eprintln!("Capturing Hue bridge traffic to {capture_file}");
+
+    Ok(())
+}
+
+impl Capture {
+    fn write(&self, entry: &CaptureEntry) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+
+        let line = match serde_json::to_string(&TimestampedEntry { timestamp, entry }) {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("Error serializing captured traffic: {e:?}");
+                return;
+            }
+        };
+
+        // The app key is mostly sent in headers, which are not recorded, but
+        // make sure it never ends up in a capture that might get shared
+        let line = if self.appkey.is_empty() {
+            line
+        } else {
+            line.replace(&self.appkey, "<redacted>")
+        };
+
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Err(e) = writeln!(file, "{line}") {
+            eprintln!("Error writing captured traffic: {e:?}");
+        }
+    }
+
+    fn t(&self) -> u128 {
+        self.start.elapsed().as_millis()
+    }
+}
+
+/// Records the raw `data` of an eventsource event
+pub fn capture_sse(data: &str) {
+    if let Some(capture) = CAPTURE.get() {
+        capture.write(&CaptureEntry::Sse {
+            t: capture.t(),
+            data,
+        });
+    }
+}
+
+/// Records the response to a GET request
+pub fn capture_get(uri: &Uri, status: StatusCode, body: &[u8]) {
+    if let Some(capture) = CAPTURE.get() {
+        capture.write(&CaptureEntry::Get {
+            t: capture.t(),
+            path: path_of(uri),
+            status: status.as_u16(),
+            body: body_to_value(body),
+        });
+    }
+}
+
+/// Records a PUT request along with its response
+pub fn capture_put(uri: &Uri, request: &str, status: StatusCode, response: &[u8]) {
+    if let Some(capture) = CAPTURE.get() {
+        capture.write(&CaptureEntry::Put {
+            t: capture.t(),
+            path: path_of(uri),
+            request: body_to_value(request.as_bytes()),
+            status: status.as_u16(),
+            response: body_to_value(response),
+        });
+    }
+}
@@ -0,0 +1,95 @@
+use std::{sync::Arc, time::Duration};
+
+use serde::Serialize;
+use tokio::{sync::RwLock, time::Instant};
+
+use crate::{protocols::mqtt::MqttClient, settings::Settings};
+
+/// State used for answering health and readiness checks
+#[derive(Clone)]
+pub struct Health {
+    /// Shared with `MqttClient::connected`
+    pub mqtt_connected: Arc<RwLock<bool>>,
+
+    /// Whether the latest attempt to fetch the full Hue bridge state succeeded
+    pub bridge_reachable: Arc<RwLock<bool>>,
+
+    /// Whether the Hue bridge state has been fetched at startup
+    pub initial_state_loaded: Arc<RwLock<bool>>,
+
+    /// Shared with `EventsourceState::prev_event_t`
+    pub prev_event_t: Arc<RwLock<Option<Instant>>>,
+
+    /// Time at which the eventsource loop last connected or received a
+    /// message of any kind. Unlike `prev_event_t`, this keeps being updated
+    /// when no lights or sensors change for a long time.
+    pub eventsource_active_t: Arc<RwLock<Instant>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HealthReport {
+    pub mqtt_connected: bool,
+    pub bridge_reachable: bool,
+    pub initial_state_loaded: bool,
+    pub last_event_age_seconds: Option<f64>,
+    pub eventsource_activity_age_seconds: f64,
+    pub eventsource_timeout_seconds: u64,
+
+    /// Whether the eventsource loop keeps reading from or reconnecting to the
+    /// Hue bridge
+    pub eventsource_alive: bool,
+}
+
+impl HealthReport {
+    /// The process is working as intended, even if it's not able to reach the
+    /// MQTT broker or Hue bridge right now
+    pub fn is_healthy(&self) -> bool {
+        self.eventsource_alive
+    }
+
+    /// The process is able to relay messages between MQTT and the Hue bridge
+    pub fn is_ready(&self) -> bool {
+        self.is_healthy()
+            && self.mqtt_connected
+            && self.bridge_reachable
+            && self.initial_state_loaded
+    }
+}
+
+impl Health {
+    pub fn new(mqtt_client: &MqttClient) -> Health {
+        Health {
+            mqtt_connected: mqtt_client.connected.clone(),
+            bridge_reachable: Default::default(),
+            initial_state_loaded: Default::default(),
+            prev_event_t: Default::default(),
+            eventsource_active_t: Arc::new(RwLock::new(Instant::now())),
+        }
+    }
+
+    pub async fn report(&self, settings: &Settings) -> HealthReport {
+        let timeout = settings.hue_bridge.eventsource_timeout_seconds;
+        let eventsource_activity_age = self.eventsource_active_t.read().await.elapsed();
+        let initial_state_loaded = *self.initial_state_loaded.read().await;
+
+        // The eventsource loop reconnects after at most `timeout` seconds
+        // without messages, allow for some slack on top of that. The loop is
+        // only started once the initial state has been loaded.
+        let eventsource_alive = !initial_state_loaded
+            || eventsource_activity_age < Duration::from_secs(timeout * 2 + 10);
+
+        HealthReport {
+            mqtt_connected: *self.mqtt_connected.read().await,
+            bridge_reachable: *self.bridge_reachable.read().await,
+            initial_state_loaded,
+            last_event_age_seconds: self
+                .prev_event_t
+                .read()
+                .await
+                .map(|prev_event_t| prev_event_t.elapsed().as_secs_f64()),
+            eventsource_activity_age_seconds: eventsource_activity_age.as_secs_f64(),
+            eventsource_timeout_seconds: timeout,
+            eventsource_alive,
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::{convert::Infallible, net::SocketAddr};
+
+use color_eyre::Result;
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+
+use crate::{health::Health, metrics::render_metrics, settings::Settings};
+
+fn mk_response(status: StatusCode, body: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+
+    response
+}
+
+async fn health_response(health: &Health, settings: &Settings, ready: bool) -> Response<Body> {
+    let report = health.report(settings).await;
+
+    let ok = if ready {
+        report.is_ready()
+    } else {
+        report.is_healthy()
+    };
+
+    let status = if ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    match serde_json::to_string(&report) {
+        Ok(json) => mk_response(status, json),
+        Err(e) => mk_response(StatusCode::INTERNAL_SERVER_ERROR, format!("{e:?}")),
+    }
+}
+
+async fn handle_request(req: Request<Body>, health: Health, settings: Settings) -> Response<Body> {
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => match render_metrics(&health).await {
+            Ok(metrics) => mk_response(StatusCode::OK, metrics),
+            Err(e) => mk_response(StatusCode::INTERNAL_SERVER_ERROR, format!("{e:?}")),
+        },
+        (&Method::GET, "/healthz") => health_response(&health, &settings, false).await,
+        (&Method::GET, "/readyz") => health_response(&health, &settings, true).await,
+        _ => mk_response(StatusCode::NOT_FOUND, "Not found".to_string()),
+    }
+}
+
+/// Starts the HTTP listener serving Prometheus metrics and health checks, if
+/// enabled in settings
+pub fn start_http_server(settings: &Settings, health: &Health) -> Result<()> {
+    let Some(http_settings) = &settings.http else {
+        return Ok(());
+    };
+
+    let addr: SocketAddr = http_settings.addr.parse()?;
+    let health = health.clone();
+    let settings = settings.clone();
+
+    let make_service = make_service_fn(move |_| {
+        let health = health.clone();
+        let settings = settings.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let health = health.clone();
+                let settings = settings.clone();
+                async move { Ok::<_, Infallible>(handle_request(req, health, settings).await) }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&addr)?.serve(make_service);
+
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            eprintln!("HTTP server error: {e:?}");
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,376 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use color_eyre::Result;
+use serde::Serialize;
+use tokio::{sync::Mutex, time::Instant};
+
+use crate::{
+    metrics::metrics,
+    mqtt::mqtt_device::{MqttDevice, SensorValue},
+    protocols::mqtt::MqttClient,
+    settings::Settings,
+};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Gesture {
+    Single,
+    Double,
+
+    /// Three or more presses. Longer sequences are rare enough in practice
+    /// that they are not worth a gesture of their own.
+    Triple,
+    Hold,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct MqttButtonGesture<'a> {
+    id: &'a str,
+    name: &'a str,
+    gesture: Gesture,
+}
+
+#[derive(Debug, Default)]
+struct GestureState {
+    /// Number of presses in the gesture so far
+    presses: u32,
+
+    pressed: bool,
+
+    /// Whether a hold gesture has been detected for the current press
+    holding: bool,
+
+    /// When the pending gesture timer fires
+    deadline: Option<Instant>,
+
+    /// Incremented on every press and release, used to cancel pending gesture
+    /// timers
+    generation: u64,
+}
+
+impl GestureState {
+    /// Completes the gesture once the button has been held down or left
+    /// released long enough
+    fn complete_gesture(&mut self) -> Gesture {
+        let gesture = if self.pressed {
+            self.holding = true;
+            Gesture::Hold
+        } else {
+            match self.presses {
+                1 => Gesture::Single,
+                2 => Gesture::Double,
+                _ => Gesture::Triple,
+            }
+        };
+
+        self.presses = 0;
+        self.deadline = None;
+
+        gesture
+    }
+}
+
+/// Detects single, double and triple presses as well as holds from the
+/// press/release edges of Hue buttons, and publishes them to the button
+/// gesture topic.
+#[derive(Clone)]
+pub struct ButtonGestures {
+    settings: Settings,
+    mqtt_client: MqttClient,
+    states: Arc<Mutex<HashMap<String, GestureState>>>,
+}
+
+impl ButtonGestures {
+    pub fn new(settings: &Settings, mqtt_client: &MqttClient) -> Self {
+        ButtonGestures {
+            settings: settings.clone(),
+            mqtt_client: mqtt_client.clone(),
+            states: Default::default(),
+        }
+    }
+
+    /// Handles a change in the pressed state of a button. Must be called in
+    /// the order in which the changes happened.
+    pub async fn handle(&self, mqtt_device: &MqttDevice) {
+        let Some(SensorValue::Button(pressed)) = mqtt_device.sensor_value else {
+            return;
+        };
+
+        let mut states = self.states.lock().await;
+        let state = states.entry(mqtt_device.id.clone()).or_default();
+
+        // Ignore repeated edges, such as a release without a preceding press
+        if state.pressed == pressed {
+            return;
+        }
+
+        // An edge arriving just as the gesture timer expires may get here
+        // before the timer does. The timer gets cancelled below, so complete
+        // the gesture here instead.
+        let completed = state
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+            .then(|| state.complete_gesture());
+
+        state.pressed = pressed;
+        state.generation += 1;
+
+        let delay_ms = if pressed {
+            state.presses += 1;
+            Some(self.settings.hue_bridge.button_hold_ms)
+        } else if state.holding {
+            // Releasing a held button ends the gesture
+            state.holding = false;
+            state.presses = 0;
+            state.deadline = None;
+            None
+        } else {
+            Some(self.settings.hue_bridge.button_gesture_window_ms)
+        };
+
+        if let Some(delay_ms) = delay_ms {
+            state.deadline = Some(Instant::now() + Duration::from_millis(delay_ms));
+            self.start_gesture_timer(mqtt_device, state.generation, delay_ms);
+        }
+
+        drop(states);
+
+        if let Some(gesture) = completed {
+            self.emit(&mqtt_device.id, &mqtt_device.name, gesture).await;
+        }
+    }
+
+    /// Emits a gesture once the button has been held down or left released for
+    /// `delay_ms`, unless the button has been pressed or released since.
+    fn start_gesture_timer(&self, mqtt_device: &MqttDevice, generation: u64, delay_ms: u64) {
+        let button_gestures = self.clone();
+        let id = mqtt_device.id.clone();
+        let name = mqtt_device.name.clone();
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+
+            let gesture = {
+                let mut states = button_gestures.states.lock().await;
+
+                let Some(state) = states.get_mut(&id) else {
+                    return;
+                };
+
+                if state.generation != generation {
+                    return;
+                }
+
+                state.complete_gesture()
+            };
+
+            button_gestures.emit(&id, &name, gesture).await;
+        });
+    }
+
+    async fn emit(&self, id: &str, name: &str, gesture: Gesture) {
+        debug!("Detected {gesture:?} gesture for {name}");
+
+        if let Err(e) = self.publish(id, name, gesture).await {
+            eprintln!("Error publishing button gesture: {e:?}");
+            metrics().mqtt_publish_failures.inc();
+        }
+    }
+
+    async fn publish(&self, id: &str, name: &str, gesture: Gesture) -> Result<()> {
+        let topic = self.settings.mqtt.button_gesture_topic.replace("{id}", id);
+
+        let json = serde_json::to_string(&MqttButtonGesture { id, name, gesture })?;
+
+        self.mqtt_client
+            .client
+            .publish(topic, rumqttc::QoS::AtLeastOnce, false, json)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rumqttc::{AsyncClient, Request};
+
+    use super::*;
+    use crate::mqtt::command_queue::CommandQueue;
+
+    const WINDOW: Duration = Duration::from_millis(400);
+    const HOLD: Duration = Duration::from_millis(800);
+
+    fn settings() -> Settings {
+        let toml = r#"
+            [hue_bridge]
+            eventsource_timeout_seconds = 300
+            button_gesture_window_ms = 400
+            button_hold_ms = 800
+
+            [mqtt]
+            id = "hue-mqtt-test"
+            host = "127.0.0.1"
+            port = 1883
+            light_topic = "home/lights/hue/{id}"
+            light_topic_set = "home/lights/hue/{id}/set"
+            sensor_topic = "home/sensors/hue/{id}"
+        "#;
+
+        config::Config::builder()
+            .add_source(config::File::from_str(toml, config::FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    struct Button {
+        gestures: ButtonGestures,
+        receiver: flume::Receiver<Request>,
+    }
+
+    impl Button {
+        fn new() -> Button {
+            let settings = settings();
+            let (sender, receiver) = flume::unbounded();
+            let mqtt_client = MqttClient {
+                client: AsyncClient::from_senders(sender),
+                command_queue: CommandQueue::new(&settings),
+                connected: Default::default(),
+            };
+
+            Button {
+                gestures: ButtonGestures::new(&settings, &mqtt_client),
+                receiver,
+            }
+        }
+
+        async fn set_pressed(&self, pressed: bool) {
+            let mqtt_device: MqttDevice = serde_json::from_str(
+                r#"{"id": "button-1", "name": "Switch button 1", "power": null}"#,
+            )
+            .unwrap();
+
+            self.gestures
+                .handle(&MqttDevice {
+                    sensor_value: Some(SensorValue::Button(pressed)),
+                    ..mqtt_device
+                })
+                .await;
+        }
+
+        async fn click(&self) {
+            self.set_pressed(true).await;
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            self.set_pressed(false).await;
+        }
+
+        /// Waits for all pending gestures, and returns the published ones
+        async fn gestures(&self) -> Vec<String> {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+
+            self.receiver
+                .drain()
+                .filter_map(|request| match request {
+                    Request::Publish(publish) => {
+                        let json: serde_json::Value =
+                            serde_json::from_slice(&publish.payload).unwrap();
+                        Some(json["gesture"].as_str().unwrap().to_string())
+                    }
+                    _ => None,
+                })
+                .collect()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn single_press() {
+        let button = Button::new();
+
+        button.click().await;
+
+        assert_eq!(button.gestures().await, ["single"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn double_press() {
+        let button = Button::new();
+
+        button.click().await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        button.click().await;
+
+        assert_eq!(button.gestures().await, ["double"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn triple_press() {
+        let button = Button::new();
+
+        for _ in 0..3 {
+            button.click().await;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        assert_eq!(button.gestures().await, ["triple"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn more_presses_are_triple() {
+        let button = Button::new();
+
+        for _ in 0..5 {
+            button.click().await;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        assert_eq!(button.gestures().await, ["triple"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn hold_and_release() {
+        let button = Button::new();
+
+        button.set_pressed(true).await;
+        tokio::time::sleep(HOLD + Duration::from_millis(500)).await;
+        button.set_pressed(false).await;
+
+        // Releasing after a hold does not produce another gesture
+        assert_eq!(button.gestures().await, ["hold"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn press_after_hold() {
+        let button = Button::new();
+
+        button.set_pressed(true).await;
+        tokio::time::sleep(HOLD).await;
+        button.set_pressed(false).await;
+        button.click().await;
+
+        assert_eq!(button.gestures().await, ["hold", "single"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn press_as_window_expires() {
+        let button = Button::new();
+
+        button.click().await;
+        tokio::time::sleep(WINDOW).await;
+        button.click().await;
+
+        assert_eq!(button.gestures().await, ["single", "single"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn press_just_before_window_expires() {
+        let button = Button::new();
+
+        button.click().await;
+        tokio::time::sleep(WINDOW - Duration::from_millis(1)).await;
+        button.click().await;
+
+        assert_eq!(button.gestures().await, ["double"]);
+    }
+}
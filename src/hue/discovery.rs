@@ -0,0 +1,150 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::RwLock,
+    time::Duration,
+};
+
+use color_eyre::Result;
+use mdns_sd::{ServiceDaemon, ServiceEvent};
+use tokio::time::timeout;
+
+use crate::settings::{HueSettings, AUTO_ADDR};
+
+const SERVICE_TYPE: &str = "_hue._tcp.local.";
+
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of consecutive failures to reach the bridge after which its address
+/// is looked up again
+const REDISCOVER_AFTER_FAILURES: u32 = 3;
+
+/// A Hue bridge found on the local network
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiscoveredBridge {
+    /// Bridge ID in lowercase, e.g. "001788fffe41dde0". This is also the
+    /// common name of the bridge's certificate.
+    pub id: String,
+
+    pub addr: SocketAddr,
+}
+
+/// The bridge currently in use, if it was found through mDNS. Requests for
+/// `https://<bridge id>/...` are sent to its address, see `BridgeResolver`.
+static DISCOVERED_BRIDGE: RwLock<Option<DiscoveredBridge>> = RwLock::new(None);
+
+pub fn discovered_bridge() -> Option<DiscoveredBridge> {
+    DISCOVERED_BRIDGE
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+/// Sends requests for `https://<bridge id>/...` to the bridge's address
+pub fn set_discovered_bridge(bridge: DiscoveredBridge) {
+    *DISCOVERED_BRIDGE.write().unwrap_or_else(|e| e.into_inner()) = Some(bridge);
+}
+
+/// Browses for Hue bridges on the local network, and returns the first one
+/// found, or the one with ID `bridge_id` if given
+pub async fn discover_bridge(bridge_id: Option<&str>) -> Result<DiscoveredBridge> {
+    let mdns = ServiceDaemon::new()?;
+    let receiver = mdns.browse(SERVICE_TYPE)?;
+
+    let result = timeout(DISCOVERY_TIMEOUT, async {
+        while let Ok(event) = receiver.recv_async().await {
+            let ServiceEvent::ServiceResolved(info) = event else {
+                continue;
+            };
+
+            let Some(id) = info.get_property_val_str("bridgeid") else {
+                continue;
+            };
+            let id = id.to_lowercase();
+
+            if bridge_id.is_some_and(|bridge_id| bridge_id != id) {
+                continue;
+            }
+
+            // Prefer IPv4, as that's what the bridge is most likely to be
+            // reachable over
+            let mut addresses: Vec<&IpAddr> = info.get_addresses().iter().collect();
+            addresses.sort_by_key(|ip| !ip.is_ipv4());
+
+            if let Some(ip) = addresses.first() {
+                return Some(DiscoveredBridge {
+                    id,
+                    addr: SocketAddr::new(**ip, info.get_port()),
+                });
+            }
+        }
+
+        None
+    })
+    .await;
+
+    // Wait for the daemon to stop, it complains if nobody is listening
+    if let Ok(status) = mdns.shutdown() {
+        let _ = status.recv_async().await;
+    }
+
+    match result {
+        Ok(Some(bridge)) => Ok(bridge),
+        Ok(None) => Err(eyre!("mDNS browsing for Hue bridges stopped unexpectedly")),
+        Err(_) => Err(match bridge_id {
+            Some(bridge_id) => eyre!("Hue bridge {bridge_id} not found through mDNS"),
+            None => eyre!("No Hue bridge found through mDNS"),
+        }),
+    }
+}
+
+/// If `addr` is set to "auto", finds the bridge through mDNS and replaces
+/// `addr` with the bridge ID and port. `BridgeResolver` resolves the bridge ID
+/// to the discovered address, and the certificate is verified against the
+/// bridge ID.
+pub async fn resolve_bridge_addr(hue_settings: &mut HueSettings) -> Result<()> {
+    if hue_settings.addr != AUTO_ADDR {
+        return Ok(());
+    }
+
+    let bridge = discover_bridge(None).await?;
+    eprintln!("Found Hue bridge {} at {}", bridge.id, bridge.addr);
+
+    hue_settings.addr = format!("{}:{}", bridge.id, bridge.addr.port());
+    set_discovered_bridge(bridge);
+
+    Ok(())
+}
+
+/// Looks up the address of the previously discovered bridge again, in case it
+/// has changed. Does nothing if the bridge address was configured by hand.
+pub async fn rediscover_bridge() -> Result<()> {
+    let Some(prev_bridge) = discovered_bridge() else {
+        return Ok(());
+    };
+
+    let bridge = discover_bridge(Some(&prev_bridge.id)).await?;
+
+    if bridge != prev_bridge {
+        eprintln!("Hue bridge {} moved to {}", bridge.id, bridge.addr);
+        set_discovered_bridge(bridge);
+    }
+
+    Ok(())
+}
+
+/// Counts a failure to reach the bridge in `failures`, which should be reset
+/// to 0 whenever the bridge is reached. Looks up the bridge address again
+/// after `REDISCOVER_AFTER_FAILURES` consecutive failures.
+pub async fn note_bridge_failure(failures: &mut u32) {
+    *failures += 1;
+
+    if *failures < REDISCOVER_AFTER_FAILURES {
+        return;
+    }
+
+    *failures = 0;
+
+    if let Err(e) = rediscover_bridge().await {
+        eprintln!("Error discovering Hue bridge: {e:?}");
+    }
+}
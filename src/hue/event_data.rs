@@ -2,13 +2,25 @@ use std::collections::HashMap;
 
 use color_eyre::Result;
 use serde::Deserialize;
-use tokio::sync::RwLock;
 
-use crate::mqtt::mqtt_device::{Ct, DeviceColor, MqttDevice, Xy};
+use crate::metrics::metrics;
+use crate::mqtt::{
+    mqtt_battery::MqttBattery,
+    mqtt_button_event::MqttButtonEvent,
+    mqtt_device::{Ct, DeviceColor, MqttDevice, SensorValue, Xy},
+    mqtt_rotary_event::MqttRotaryEvent,
+    mqtt_scene::MqttScene,
+};
 
+use super::events::EventsourceState;
 use super::rest::{
     button::ButtonEventData,
+    common::Owner,
+    device_power::PowerStateData,
     light::{ColorData, ColorTemperatureData, DimmingData, OnData},
+    relative_rotary::RelativeRotaryEventData,
+    scene::{SceneMetadata, SceneStatus},
+    zigbee_connectivity::is_available_status,
 };
 
 #[derive(Deserialize, Debug, Clone)]
@@ -26,6 +38,13 @@ struct LightUpdateData {
     color_temperature: Option<ColorTemperatureData>,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+struct GroupedLightUpdateData {
+    id: String,
+    on: Option<OnData>,
+    dimming: Option<DimmingData>,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 struct MotionData {
     motion: bool,
@@ -60,7 +79,29 @@ struct LightLevelUpdateData {
 }
 
 #[derive(Deserialize, Debug, Clone)]
-struct DevicePowerData {}
+struct SceneUpdateData {
+    id: String,
+    metadata: Option<SceneMetadata>,
+    status: Option<SceneStatus>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct ZigbeeConnectivityUpdateData {
+    owner: Option<Owner>,
+    status: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct DevicePowerUpdateData {
+    id: String,
+    power_state: Option<PowerStateData>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct RelativeRotaryUpdateData {
+    id: String,
+    relative_rotary: RelativeRotaryEventData,
+}
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -70,11 +111,16 @@ enum UpdateData {
     Motion(MotionUpdateData),
     Temperature(TemperatureUpdateData),
     LightLevel(LightLevelUpdateData),
-
-    // Ignored updates
-    DevicePower,        // Battery level update
-    GroupedLight,       // Light groups update
-    ZigbeeConnectivity, // Connectivity issue update
+    GroupedLight(GroupedLightUpdateData),
+    Scene(SceneUpdateData),
+    ZigbeeConnectivity(ZigbeeConnectivityUpdateData),
+    DevicePower(DevicePowerUpdateData),
+    RelativeRotary(RelativeRotaryUpdateData),
+
+    /// Resource types that we don't handle, such as entertainment or
+    /// bridge_home
+    #[serde(other)]
+    Unknown,
 }
 
 impl UpdateData {
@@ -99,7 +145,7 @@ impl UpdateData {
                 }
 
                 if let Some(sensor_value) = sensor_value {
-                    mqtt_device.sensor_value = Some(sensor_value.to_string());
+                    mqtt_device.sensor_value = Some(SensorValue::Button(sensor_value));
                     mqtt_device.updated = Some(button.button.button_report.updated.clone());
 
                     return Some(mqtt_device);
@@ -108,21 +154,24 @@ impl UpdateData {
             UpdateData::Motion(motion) => {
                 let mut mqtt_device = mqtt_devices.get(&motion.id)?.clone();
 
-                mqtt_device.sensor_value = Some(motion.motion.motion.to_string());
+                mqtt_device.sensor_value = Some(SensorValue::Motion(motion.motion.motion));
 
                 return Some(mqtt_device);
             }
             UpdateData::Temperature(temperature) => {
                 let mut mqtt_device = mqtt_devices.get(&temperature.id)?.clone();
 
-                mqtt_device.sensor_value = Some(temperature.temperature.temperature.to_string());
+                mqtt_device.sensor_value = Some(SensorValue::Temperature(
+                    temperature.temperature.temperature,
+                ));
 
                 return Some(mqtt_device);
             }
             UpdateData::LightLevel(light_level) => {
                 let mut mqtt_device = mqtt_devices.get(&light_level.id)?.clone();
 
-                mqtt_device.sensor_value = Some(light_level.light.light_level.to_string());
+                mqtt_device.sensor_value =
+                    Some(SensorValue::LightLevel(light_level.light.light_level));
 
                 return Some(mqtt_device);
             }
@@ -140,7 +189,7 @@ impl UpdateData {
                     mirek: Some(mirek), ..
                 }) = light.color_temperature
                 {
-                    let ct = (1_000_000.0 / mirek) as u16;
+                    let ct = (1_000_000.0 / mirek).round() as u16;
                     mqtt_device.color = Some(DeviceColor::Ct(Ct { ct }));
                 }
 
@@ -154,130 +203,407 @@ impl UpdateData {
 
                 return Some(mqtt_device);
             }
+            UpdateData::GroupedLight(grouped_light) => {
+                let mut mqtt_device = mqtt_devices.get(&grouped_light.id)?.clone();
+
+                if let Some(on) = &grouped_light.on {
+                    mqtt_device.power = Some(on.on);
+                }
+
+                if let Some(dimming) = &grouped_light.dimming {
+                    mqtt_device.brightness = Some(dimming.brightness / 100.0)
+                }
+
+                return Some(mqtt_device);
+            }
 
             _ => {}
         };
 
         None
     }
+
+    /// Computes current scene state from previous scene state and an
+    /// UpdateData containing the changed fields.
+    fn to_mqtt_scene(&self, mqtt_scenes: &HashMap<String, MqttScene>) -> Option<MqttScene> {
+        let UpdateData::Scene(scene) = self else {
+            return None;
+        };
+
+        let mut mqtt_scene = mqtt_scenes.get(&scene.id)?.clone();
+
+        if let Some(metadata) = &scene.metadata {
+            mqtt_scene.name = metadata.name.clone();
+        }
+
+        if let Some(status) = &scene.status {
+            mqtt_scene.status = Some(status.active.clone());
+        }
+
+        Some(mqtt_scene)
+    }
+
+    /// Computes current battery state from previous battery state and an
+    /// UpdateData containing the changed fields.
+    fn to_mqtt_battery(
+        &self,
+        mqtt_batteries: &HashMap<String, MqttBattery>,
+    ) -> Option<MqttBattery> {
+        let UpdateData::DevicePower(device_power) = self else {
+            return None;
+        };
+
+        let mut mqtt_battery = mqtt_batteries.get(&device_power.id)?.clone();
+        let power_state = device_power.power_state.as_ref()?;
+
+        if let Some(battery_level) = power_state.battery_level {
+            mqtt_battery.battery_level = Some(battery_level);
+        }
+
+        if let Some(battery_state) = &power_state.battery_state {
+            mqtt_battery.battery_state = Some(battery_state.clone());
+        }
+
+        Some(mqtt_battery)
+    }
+
+    /// Returns the rotation contained in a relative rotary update.
+    fn to_mqtt_rotary_event(
+        &self,
+        rotary_names: &HashMap<String, String>,
+    ) -> Option<MqttRotaryEvent> {
+        let UpdateData::RelativeRotary(relative_rotary) = self else {
+            return None;
+        };
+
+        let name = rotary_names.get(&relative_rotary.id)?;
+
+        MqttRotaryEvent::new(&relative_rotary.id, name, &relative_rotary.relative_rotary)
+    }
+
+    /// Returns the owning device ID and availability of a device whose zigbee
+    /// connectivity status has changed.
+    fn to_device_availability(&self) -> Option<(String, bool)> {
+        let UpdateData::ZigbeeConnectivity(zigbee_connectivity) = self else {
+            return None;
+        };
+
+        let owner = zigbee_connectivity.owner.as_ref()?;
+        let status = zigbee_connectivity.status.as_ref()?;
+
+        Some((owner.rid.clone(), is_available_status(status)))
+    }
 }
 
+/// Resources contained in an eventsource event. These are decoded one by one,
+/// so that a single resource we fail to decode doesn't cause the rest of the
+/// event to be dropped.
 #[derive(Deserialize, Debug, Clone)]
-pub struct UpdateEvent {
-    data: Vec<UpdateData>,
+pub struct HueEventData {
+    data: Vec<serde_json::Value>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum HueEvent {
-    Update(UpdateEvent),
+    Update(HueEventData),
+    Add(HueEventData),
+    Delete(HueEventData),
+    Error(HueEventData),
+
+    #[serde(other)]
+    Unknown,
+}
+
+/// Changed state resulting from handling a batch of Hue eventsource events
+#[derive(Debug, Default)]
+pub struct HueEventUpdates {
+    pub devices: Vec<MqttDevice>,
+    pub scenes: Vec<MqttScene>,
+    pub batteries: Vec<MqttBattery>,
+    pub button_events: Vec<MqttButtonEvent>,
+    pub rotary_events: Vec<MqttRotaryEvent>,
+
+    /// Changes in the pressed state of buttons, in the order they happened.
+    /// These are also included in `devices`.
+    pub buttons: Vec<MqttDevice>,
+
+    /// Availability of Hue devices, keyed by device ID
+    pub availability: HashMap<String, bool>,
+
+    /// Whether resources have been added to or removed from the Hue bridge
+    pub resources_changed: bool,
+}
+
+/// Returns a short description of a resource contained in an eventsource
+/// event, for logging purposes
+fn describe_resource(data: &serde_json::Value) -> String {
+    let rtype = data
+        .get("type")
+        .and_then(|x| x.as_str())
+        .unwrap_or("unknown");
+    let id = data.get("id").and_then(|x| x.as_str()).unwrap_or("unknown");
+
+    format!("{rtype} {id}")
+}
+
+/// Decodes a single resource contained in an eventsource update event.
+/// Resource types that we don't handle are counted in the `updates_skipped`
+/// metric.
+fn decode_update_data(data: &serde_json::Value) -> Result<Option<UpdateData>> {
+    match UpdateData::deserialize(data) {
+        Ok(UpdateData::Unknown) => {
+            let rtype = data
+                .get("type")
+                .and_then(|x| x.as_str())
+                .unwrap_or("unknown")
+                .to_string();
+
+            let skipped = metrics().updates_skipped.with_label_values(&[&rtype]);
+
+            if skipped.get() == 0 {
+                warn!("Ignoring updates of unsupported Hue resource type {rtype}");
+            }
+
+            skipped.inc();
+
+            Ok(None)
+        }
+        Ok(update_data) => {
+            let rtype = data
+                .get("type")
+                .and_then(|x| x.as_str())
+                .unwrap_or("unknown");
+            metrics().events_handled.with_label_values(&[rtype]).inc();
+
+            Ok(Some(update_data))
+        }
+        Err(e) => {
+            eprintln!(
+                "Error decoding Hue {} update: {e}\n{}",
+                describe_resource(data),
+                serde_json::to_string_pretty(data)?
+            );
+
+            Ok(None)
+        }
+    }
 }
 
 pub async fn handle_incoming_hue_events(
-    mqtt_devices: &RwLock<HashMap<String, MqttDevice>>,
+    state: &EventsourceState,
     events: String,
     ignore_buttons: bool,
-) -> Result<Vec<MqttDevice>> {
-    let serde_json_value: serde_json::Value = serde_json::from_str(&events)?;
-    let result = serde_json::from_str::<Vec<HueEvent>>(&events);
-
-    match result {
-        Ok(events) => {
-            let update_data_vec: Vec<UpdateData> = events
-                .into_iter()
-                .flat_map(|event| {
-                    let HueEvent::Update(event) = event;
-
-                    event.data
-                })
-                .collect();
-
-            // We only want light_updates to contain the the result of applying
-            // all incoming device state updates. This way we don't spam mqtt
-            // with the Hue bridge's "halfway" state updates.
-            //
-            // Hue's eventsource API splits a light update into multiple
-            // "UpdateData" chunks, with each chunk containing the change to a
-            // single field. So if we send one HTTP request simultaneously
-            // changing a light's power state, color and brightness, you will
-            // get back three events, one for each field.
-            let mut light_updates: HashMap<String, MqttDevice> = HashMap::new();
-            for data in update_data_vec
-                .iter()
-                .filter(|data| matches!(data, UpdateData::Light(_)))
-            {
-                let mut mqtt_devices = mqtt_devices.write().await;
-                let mqtt_device = data.to_mqtt_device(&mqtt_devices);
+) -> Result<HueEventUpdates> {
+    let events: Vec<serde_json::Value> = serde_json::from_str(&events)?;
+
+    let mut update_data_vec: Vec<UpdateData> = vec![];
+    let mut resources_changed = false;
+
+    for event in &events {
+        let hue_event = match HueEvent::deserialize(event) {
+            Ok(hue_event) => hue_event,
+            Err(e) => {
+                eprintln!(
+                    "Error decoding Hue event: {e}\n{}",
+                    serde_json::to_string_pretty(event)?
+                );
+                continue;
+            }
+        };
 
-                if let Some(mqtt_device) = &mqtt_device {
-                    // Store device state as computed from previous state and
-                    // the event being handled
-                    mqtt_devices.insert(mqtt_device.id.clone(), mqtt_device.clone());
-                    light_updates.insert(mqtt_device.id.clone(), mqtt_device.clone());
+        match hue_event {
+            HueEvent::Update(hue_event) => {
+                for data in &hue_event.data {
+                    if let Some(update_data) = decode_update_data(data)? {
+                        update_data_vec.push(update_data);
+                    }
                 }
             }
+            HueEvent::Add(hue_event) => {
+                for data in &hue_event.data {
+                    debug!("Hue resource added: {}", describe_resource(data));
+                }
+
+                resources_changed = true;
+            }
+            HueEvent::Delete(hue_event) => {
+                for data in &hue_event.data {
+                    debug!("Hue resource deleted: {}", describe_resource(data));
+                }
 
-            // We want sensor_updates to contain all intermediate device states.
-            // The reason is that we want to inform mqtt clients of situations
-            // where sensor state rapidly changes between two values (such as
-            // when pressing a button in rapid succession).
-            //
-            // For example, if a switch is pressed four times with just under
-            // 1s between presses, due to the Hue bridge's debouncing of the
-            // eventsource API events, we get sent the following sequence of
-            // messages of the switch pressed state:
-            //
-            // [true], [false, true, false], [true, false], [true, false]
-            //
-            // If we were to only forward the trailing value of each message,
-            // mqtt would only see [true, false, false, false].
-
-            let sensor_updates: Vec<MqttDevice> = {
-                let mut mqtt_devices = mqtt_devices.write().await;
-                update_data_vec
-                    .iter()
-                    .filter(|data| {
-                        (matches!(data, UpdateData::Button(_)) && !ignore_buttons)
-                            | matches!(data, UpdateData::Motion(_))
-                            | matches!(data, UpdateData::Temperature(_))
-                            | matches!(data, UpdateData::LightLevel(_))
-                    })
-                    .filter(|data| match data {
-                        UpdateData::Button(button) => {
-                            // Ignore all other button presses from the
-                            // eventsource API. Button resource polling will
-                            // handle the other cases.
-                            button.button.last_event == "initial_press"
-                        }
-                        _ => true,
-                    })
-                    // Only filter data if ignore_buttons flag is set to false and event is button related, otherwise we ignore any button updates for now
-                    // Sensors are filtered as normal
-                    .filter_map(|data| {
-                        let mqtt_device = data.to_mqtt_device(&mqtt_devices);
-
-                        if let Some(mqtt_device) = &mqtt_device {
-                            mqtt_devices.insert(mqtt_device.id.clone(), mqtt_device.clone());
-                        }
-
-                        mqtt_device
-                    })
-                    .collect()
-            };
-
-            let updates = light_updates
-                .into_values()
-                .chain(sensor_updates.into_iter())
-                .collect();
-
-            Ok(updates)
+                resources_changed = true;
+            }
+            HueEvent::Error(hue_event) => {
+                for data in &hue_event.data {
+                    eprintln!(
+                        "Hue bridge reported an error for {}:\n{}",
+                        describe_resource(data),
+                        serde_json::to_string_pretty(data)?
+                    );
+                }
+            }
+            HueEvent::Unknown => {
+                eprintln!(
+                    "Got unknown event:\n{}",
+                    serde_json::to_string_pretty(event)?
+                );
+            }
         }
-        Err(e) => {
-            eprintln!(
-                "Got unknown event:\n{}",
-                serde_json::to_string_pretty(&serde_json_value)?
-            );
-            Err(eyre!(e))
+    }
+
+    // We only want light_updates to contain the the result of applying
+    // all incoming device state updates. This way we don't spam mqtt
+    // with the Hue bridge's "halfway" state updates.
+    //
+    // Hue's eventsource API splits a light update into multiple
+    // "UpdateData" chunks, with each chunk containing the change to a
+    // single field. So if we send one HTTP request simultaneously
+    // changing a light's power state, color and brightness, you will
+    // get back three events, one for each field. The same applies to
+    // grouped lights.
+    let mut light_updates: HashMap<String, MqttDevice> = HashMap::new();
+    for data in update_data_vec
+        .iter()
+        .filter(|data| matches!(data, UpdateData::Light(_) | UpdateData::GroupedLight(_)))
+    {
+        let mut mqtt_devices = state.mqtt_devices.write().await;
+        let mqtt_device = data.to_mqtt_device(&mqtt_devices);
+
+        if let Some(mqtt_device) = &mqtt_device {
+            // Store device state as computed from previous state and
+            // the event being handled
+            mqtt_devices.insert(mqtt_device.id.clone(), mqtt_device.clone());
+            light_updates.insert(mqtt_device.id.clone(), mqtt_device.clone());
         }
     }
+
+    // We want sensor_updates to contain all intermediate device states.
+    // The reason is that we want to inform mqtt clients of situations
+    // where sensor state rapidly changes between two values (such as
+    // when pressing a button in rapid succession).
+    //
+    // For example, if a switch is pressed four times with just under
+    // 1s between presses, due to the Hue bridge's debouncing of the
+    // eventsource API events, we get sent the following sequence of
+    // messages of the switch pressed state:
+    //
+    // [true], [false, true, false], [true, false], [true, false]
+    //
+    // If we were to only forward the trailing value of each message,
+    // mqtt would only see [true, false, false, false].
+
+    let mut button_updates: Vec<MqttDevice> = vec![];
+
+    let sensor_updates: Vec<MqttDevice> = {
+        let mut mqtt_devices = state.mqtt_devices.write().await;
+        update_data_vec
+            .iter()
+            .filter(|data| {
+                (matches!(data, UpdateData::Button(_)) && !ignore_buttons)
+                    | matches!(data, UpdateData::Motion(_))
+                    | matches!(data, UpdateData::Temperature(_))
+                    | matches!(data, UpdateData::LightLevel(_))
+            })
+            .filter(|data| match data {
+                UpdateData::Button(button) => {
+                    // Ignore all other button presses from the
+                    // eventsource API. Button resource polling will
+                    // handle the other cases.
+                    button.button.last_event == "initial_press"
+                }
+                _ => true,
+            })
+            // Only filter data if ignore_buttons flag is set to false and event is button related, otherwise we ignore any button updates for now
+            // Sensors are filtered as normal
+            .filter_map(|data| {
+                let mqtt_device = data.to_mqtt_device(&mqtt_devices);
+
+                if let Some(mqtt_device) = &mqtt_device {
+                    mqtt_devices.insert(mqtt_device.id.clone(), mqtt_device.clone());
+
+                    if matches!(data, UpdateData::Button(_)) {
+                        button_updates.push(mqtt_device.clone());
+                    }
+                }
+
+                mqtt_device
+            })
+            .collect()
+    };
+
+    let scene_updates: HashMap<String, MqttScene> = {
+        let mut mqtt_scenes = state.mqtt_scenes.write().await;
+        update_data_vec
+            .iter()
+            .filter_map(|data| {
+                let mqtt_scene = data.to_mqtt_scene(&mqtt_scenes)?;
+                mqtt_scenes.insert(mqtt_scene.id.clone(), mqtt_scene.clone());
+
+                Some((mqtt_scene.id.clone(), mqtt_scene))
+            })
+            .collect()
+    };
+
+    // Unlike sensor_value, button events are published regardless of
+    // whether button polling is active, as button events are never
+    // published more than once.
+    let button_events: Vec<MqttButtonEvent> = {
+        let mut mqtt_devices = state.mqtt_devices.write().await;
+        update_data_vec
+            .iter()
+            .filter_map(|data| match data {
+                UpdateData::Button(button) => MqttButtonEvent::from_report(
+                    mqtt_devices.get_mut(&button.id)?,
+                    &button.button.button_report,
+                ),
+                _ => None,
+            })
+            .collect()
+    };
+
+    let rotary_events: Vec<MqttRotaryEvent> = {
+        let rotary_names = state.rotary_names.read().await;
+        update_data_vec
+            .iter()
+            .filter_map(|data| data.to_mqtt_rotary_event(&rotary_names))
+            .collect()
+    };
+
+    let battery_updates: HashMap<String, MqttBattery> = {
+        let mut mqtt_batteries = state.mqtt_batteries.write().await;
+        update_data_vec
+            .iter()
+            .filter_map(|data| match data {
+                UpdateData::DevicePower(device_power) => {
+                    let mqtt_battery = data.to_mqtt_battery(&mqtt_batteries)?;
+                    mqtt_batteries.insert(device_power.id.clone(), mqtt_battery.clone());
+
+                    Some((mqtt_battery.id.clone(), mqtt_battery))
+                }
+                _ => None,
+            })
+            .collect()
+    };
+
+    let devices = light_updates
+        .into_values()
+        .chain(sensor_updates.into_iter())
+        .collect();
+
+    let availability = update_data_vec
+        .iter()
+        .filter_map(|data| data.to_device_availability())
+        .collect();
+
+    Ok(HueEventUpdates {
+        devices,
+        scenes: scene_updates.into_values().collect(),
+        batteries: battery_updates.into_values().collect(),
+        button_events,
+        rotary_events,
+        buttons: button_updates,
+        availability,
+        resources_changed,
+    })
 }
@@ -1,4 +1,8 @@
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
 
 use eyre::{OptionExt, Result};
 use futures::StreamExt;
@@ -8,7 +12,17 @@ use tokio::{
 };
 
 use crate::{
-    mqtt::mqtt_device::{publish_mqtt_devices, MqttDevice},
+    capture::capture_sse,
+    health::Health,
+    metrics::metrics,
+    mqtt::{
+        availability::publish_device_availability,
+        mqtt_battery::{publish_mqtt_batteries, MqttBattery},
+        mqtt_button_event::publish_mqtt_button_events,
+        mqtt_device::{publish_mqtt_devices, MqttDevice},
+        mqtt_rotary_event::publish_mqtt_rotary_events,
+        mqtt_scene::{publish_mqtt_scenes, MqttScene},
+    },
     protocols::{
         eventsource::{mk_eventsource_stream, PinnedEventSourceStream},
         https::HyperHttpsClient,
@@ -18,16 +32,81 @@ use crate::{
 };
 
 use super::{
-    event_data::handle_incoming_hue_events, init_state::init_state_to_mqtt_devices,
-    polling::poll_hue_buttons, rest::HueState,
+    button_gestures::ButtonGestures,
+    event_data::handle_incoming_hue_events,
+    init_state::{
+        init_state_to_mqtt_batteries, init_state_to_mqtt_devices, init_state_to_mqtt_scenes,
+        init_state_to_rotary_names,
+    },
+    pending_commands::{start_pending_commands_loop, PendingCommands},
+    polling::poll_hue_buttons,
+    rest::HueState,
 };
 
+/// State shared between the eventsource and button polling tasks
+#[derive(Clone)]
+pub struct EventsourceState {
+    /// Time at which the previous eventsource event was received
+    pub prev_event_t: Arc<RwLock<Option<Instant>>>,
+
+    /// Somewhat annoyingly, the Hue eventsource endpoint returns changed
+    /// fields of a device in individual chunks. We need to persist these
+    /// changes across incoming events to be able to piece together current
+    /// device state.
+    pub mqtt_devices: Arc<RwLock<HashMap<String, MqttDevice>>>,
+    pub mqtt_scenes: Arc<RwLock<HashMap<String, MqttScene>>>,
+
+    /// Battery state of devices, keyed by device_power resource ID
+    pub mqtt_batteries: Arc<RwLock<HashMap<String, MqttBattery>>>,
+
+    /// Names of relative rotary resources, keyed by resource ID
+    pub rotary_names: Arc<RwLock<HashMap<String, String>>>,
+
+    /// Notify channel is used to send a notification to the polling task that
+    /// a Hue bridge event of any kind was received
+    pub notify: Arc<Notify>,
+
+    /// IDs of Hue devices, used to clear retained per-device topics once a
+    /// device is removed from the bridge
+    pub device_ids: Arc<RwLock<HashSet<String>>>,
+
+    /// Notify channel is used to send a notification to the state polling
+    /// task that resources were added to or removed from the bridge
+    pub resync: Arc<Notify>,
+
+    pub pending_commands: PendingCommands,
+    pub button_gestures: ButtonGestures,
+    pub health: Health,
+}
+
+impl EventsourceState {
+    pub fn new(
+        settings: &Settings,
+        mqtt_client: &MqttClient,
+        init_state: &HueState,
+        pending_commands: &PendingCommands,
+        health: &Health,
+    ) -> EventsourceState {
+        EventsourceState {
+            prev_event_t: health.prev_event_t.clone(),
+            mqtt_devices: Arc::new(RwLock::new(init_state_to_mqtt_devices(init_state))),
+            mqtt_scenes: Arc::new(RwLock::new(init_state_to_mqtt_scenes(init_state))),
+            mqtt_batteries: Arc::new(RwLock::new(init_state_to_mqtt_batteries(init_state))),
+            rotary_names: Arc::new(RwLock::new(init_state_to_rotary_names(init_state))),
+            device_ids: Arc::new(RwLock::new(init_state.devices.keys().cloned().collect())),
+            resync: Arc::new(Notify::new()),
+            notify: Arc::new(Notify::new()),
+            pending_commands: pending_commands.clone(),
+            button_gestures: ButtonGestures::new(settings, mqtt_client),
+            health: health.clone(),
+        }
+    }
+}
+
 async fn read_and_handle_eventsource_event(
     settings: &Settings,
     mqtt_client: &MqttClient,
-    prev_event_t: &Arc<RwLock<Option<Instant>>>,
-    mqtt_devices: &Arc<RwLock<HashMap<String, MqttDevice>>>,
-    notify: &Arc<Notify>,
+    state: &EventsourceState,
     eventsource_stream: &mut PinnedEventSourceStream,
 ) -> Result<()> {
     let e = eventsource_stream
@@ -40,24 +119,37 @@ async fn read_and_handle_eventsource_event(
         return Ok(());
     };
 
+    capture_sse(&e.data);
+
+    handle_eventsource_event(settings, mqtt_client, state, e.data).await
+}
+
+/// Handles the raw `data` of a single eventsource event and publishes the
+/// resulting changes to MQTT
+pub async fn handle_eventsource_event(
+    settings: &Settings,
+    mqtt_client: &MqttClient,
+    state: &EventsourceState,
+    data: String,
+) -> Result<()> {
     // Check whether we should be ignoring button events
     let ignore_buttons = {
-        let prev_event_t = prev_event_t.read().await;
+        let prev_event_t = state.prev_event_t.read().await;
         prev_event_t
             .map(|prev_event_t| prev_event_t.elapsed() < Duration::from_millis(1500))
             .unwrap_or(false)
     };
 
-    let result = handle_incoming_hue_events(mqtt_devices, e.data, ignore_buttons).await;
+    let result = handle_incoming_hue_events(state, data, ignore_buttons).await;
 
     {
-        let mut prev_event_t = prev_event_t.write().await;
+        let mut prev_event_t = state.prev_event_t.write().await;
         *prev_event_t = Some(Instant::now());
     }
 
     // Ignore errors in the eventsource event handling
-    let mqtt_devices = match result {
-        Ok(mqtt_devices) => mqtt_devices,
+    let updates = match result {
+        Ok(updates) => updates,
         Err(e) => {
             eprintln!("Error handling incoming Hue eventsource event: {e:?}");
             return Ok(());
@@ -65,12 +157,63 @@ async fn read_and_handle_eventsource_event(
     };
 
     // Send a notification to the polling task that an event has just arrived
-    notify.notify_one();
+    state.notify.notify_one();
 
-    let result = publish_mqtt_devices(mqtt_client, settings, mqtt_devices).await;
+    if updates.resources_changed {
+        state.resync.notify_one();
+    }
+
+    for mqtt_device in &updates.devices {
+        state.pending_commands.acknowledge(mqtt_device).await;
+    }
+
+    for mqtt_device in &updates.buttons {
+        state.button_gestures.handle(mqtt_device).await;
+    }
+
+    let result = publish_mqtt_devices(mqtt_client, settings, updates.devices).await;
 
     if let Err(e) = result {
         eprintln!("Error publishing mqtt devices: {e:?}");
+        metrics().mqtt_publish_failures.inc();
+    }
+
+    let result = publish_mqtt_button_events(mqtt_client, settings, updates.button_events).await;
+
+    if let Err(e) = result {
+        eprintln!("Error publishing mqtt button events: {e:?}");
+        metrics().mqtt_publish_failures.inc();
+    }
+
+    let result = publish_mqtt_rotary_events(mqtt_client, settings, updates.rotary_events).await;
+
+    if let Err(e) = result {
+        eprintln!("Error publishing mqtt rotary events: {e:?}");
+        metrics().mqtt_publish_failures.inc();
+    }
+
+    let result = publish_mqtt_scenes(mqtt_client, settings, updates.scenes).await;
+
+    if let Err(e) = result {
+        eprintln!("Error publishing mqtt scenes: {e:?}");
+        metrics().mqtt_publish_failures.inc();
+    }
+
+    let result = publish_mqtt_batteries(mqtt_client, settings, updates.batteries).await;
+
+    if let Err(e) = result {
+        eprintln!("Error publishing mqtt batteries: {e:?}");
+        metrics().mqtt_publish_failures.inc();
+    }
+
+    for (device_id, available) in updates.availability {
+        let result =
+            publish_device_availability(mqtt_client, settings, &device_id, available).await;
+
+        if let Err(e) = result {
+            eprintln!("Error publishing device availability: {e:?}");
+            metrics().mqtt_publish_failures.inc();
+        }
     }
 
     Ok(())
@@ -80,19 +223,16 @@ pub async fn eventsource_loop(
     settings: &Settings,
     mqtt_client: &MqttClient,
     https_client: &HyperHttpsClient,
-    prev_event_t: &Arc<RwLock<Option<Instant>>>,
-    mqtt_devices: &Arc<RwLock<HashMap<String, MqttDevice>>>,
-    notify: &Arc<Notify>,
+    state: &EventsourceState,
 ) -> Result<()> {
     let mut eventsource_stream = mk_eventsource_stream(settings, https_client)?;
+    *state.health.eventsource_active_t.write().await = Instant::now();
 
     loop {
         let future = read_and_handle_eventsource_event(
             settings,
             mqtt_client,
-            prev_event_t,
-            mqtt_devices,
-            notify,
+            state,
             &mut eventsource_stream,
         );
 
@@ -100,7 +240,9 @@ pub async fn eventsource_loop(
             Duration::from_secs(settings.hue_bridge.eventsource_timeout_seconds),
             future,
         )
-        .await??
+        .await??;
+
+        *state.health.eventsource_active_t.write().await = Instant::now();
     }
 }
 
@@ -109,79 +251,76 @@ pub fn start_hue_events_loop(
     mqtt_client: &MqttClient,
     https_client: &HyperHttpsClient,
     init_state: &HueState,
-) {
+    pending_commands: &PendingCommands,
+    health: &Health,
+) -> EventsourceState {
     let mqtt_client = mqtt_client.clone();
     let settings = settings.clone();
     let https_client = https_client.clone();
-    let init_state = init_state.clone();
 
-    // Somewhat annoyingly, the Hue eventsource endpoint returns changed fields
-    // of a device in individual chunks. We need to persist these changes across
-    // incoming events to be able to piece together current device state.
-    let mqtt_devices = Arc::new(RwLock::new(init_state_to_mqtt_devices(&init_state)));
+    let state = EventsourceState::new(
+        &settings,
+        &mqtt_client,
+        init_state,
+        pending_commands,
+        health,
+    );
 
-    // Notify channel is used to send a notification to the polling task that a
-    // Hue bridge event of any kind was received
-    let notify = Arc::new(Notify::new());
-    let prev_event_t: Arc<RwLock<Option<Instant>>> = Default::default();
+    start_pending_commands_loop(&settings, &mqtt_client, &state);
 
     {
         let https_client = https_client.clone();
         let mqtt_client = mqtt_client.clone();
-        let mqtt_devices = mqtt_devices.clone();
         let settings = settings.clone();
-        let notify = notify.clone();
-        let prev_event_t = prev_event_t.clone();
+        let state = state.clone();
 
         tokio::spawn(async move {
             loop {
-                let result = eventsource_loop(
-                    &settings,
-                    &mqtt_client,
-                    &https_client,
-                    &prev_event_t,
-                    &mqtt_devices,
-                    &notify,
-                )
-                .await;
+                let result = eventsource_loop(&settings, &mqtt_client, &https_client, &state).await;
 
                 if let Err(e) = result {
                     eprintln!(
                         "Error encountered in eventsource loop: {:?}, reconnecting",
                         e
                     );
+                    metrics().eventsource_reconnects.inc();
                     tokio::time::sleep(Duration::from_secs(5)).await;
                 }
             }
         });
     }
 
-    tokio::spawn(async move {
-        loop {
-            // Wait for incoming event notifications
-            notify.notified().await;
+    {
+        let state = state.clone();
+
+        tokio::spawn(async move {
+            loop {
+                // Wait for incoming event notifications
+                state.notify.notified().await;
 
-            // Sleep some time between the event arriving and starting to poll - it
-            // is unlikely that state has changed this quickly
-            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+                // Sleep some time between the event arriving and starting to poll - it
+                // is unlikely that state has changed this quickly
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
 
-            let mut interval = tokio::time::interval(Duration::from_millis(250));
-            let prev_event_t = *prev_event_t.read().await;
+                let mut interval = tokio::time::interval(Duration::from_millis(250));
+                let prev_event_t = *state.prev_event_t.read().await;
 
-            if let Some(prev_event_t) = prev_event_t {
-                // Start polling for Hue bridge button state
-                while prev_event_t.elapsed() < Duration::from_millis(1500) {
-                    interval.tick().await;
+                if let Some(prev_event_t) = prev_event_t {
+                    // Start polling for Hue bridge button state
+                    while prev_event_t.elapsed() < Duration::from_millis(1500) {
+                        interval.tick().await;
 
-                    let result =
-                        poll_hue_buttons(&settings, &mqtt_client, &https_client, &mqtt_devices)
-                            .await;
+                        let result =
+                            poll_hue_buttons(&settings, &mqtt_client, &https_client, &state).await;
 
-                    if let Err(e) = result {
-                        eprintln!("{:?}", e);
+                        if let Err(e) = result {
+                            eprintln!("{:?}", e);
+                        }
                     }
                 }
             }
-        }
-    });
+        });
+    }
+
+    state
 }
@@ -1,13 +1,35 @@
 use std::collections::HashMap;
 
-use super::rest::{light::ColorTemperatureData, HueState};
+use super::rest::{common::Owner, light::ColorTemperatureData, HueState};
 use crate::{
-    mqtt::mqtt_device::{Capabilities, Ct, DeviceColor, MqttDevice, MqttDeviceBuilder, Xy},
+    mqtt::{
+        availability::publish_device_availability,
+        mqtt_battery::{publish_mqtt_battery, MqttBattery},
+        mqtt_device::{
+            publish_mqtt_device, Capabilities, Ct, DeviceColor, Gamut, MqttDevice,
+            MqttDeviceBuilder, SensorValue, Xy,
+        },
+        mqtt_scene::{publish_mqtt_scene, MqttScene},
+    },
     protocols::mqtt::MqttClient,
     settings::Settings,
 };
 use color_eyre::Result;
-use rumqttc::QoS;
+
+/// Returns the name of the room or zone referred to by `group`
+fn group_name(init_state: &HueState, group: &Owner) -> Option<String> {
+    match group.rtype.as_str() {
+        "room" => init_state
+            .rooms
+            .get(&group.rid)
+            .map(|room| room.metadata.name.clone()),
+        "zone" => init_state
+            .zones
+            .get(&group.rid)
+            .map(|zone| zone.metadata.name.clone()),
+        _ => None,
+    }
+}
 
 pub fn init_state_to_mqtt_devices(init_state: &HueState) -> HashMap<String, MqttDevice> {
     let mut mqtt_devices: HashMap<String, MqttDevice> = HashMap::new();
@@ -37,7 +59,7 @@ pub fn init_state_to_mqtt_devices(init_state: &HueState) -> HashMap<String, Mqtt
                 mirek: Some(mirek), ..
             }) = light.color_temperature
             {
-                let ct = (1_000_000.0 / mirek) as u16;
+                let ct = (1_000_000.0 / mirek).round() as u16;
                 builder.color(DeviceColor::Ct(Ct { ct }));
             }
 
@@ -62,6 +84,24 @@ pub fn init_state_to_mqtt_devices(init_state: &HueState) -> HashMap<String, Mqtt
                     min_ct..max_ct
                 }),
                 xy: light.color.is_some(),
+                gamut: light
+                    .color
+                    .as_ref()
+                    .and_then(|color| color.gamut.as_ref())
+                    .map(|gamut| Gamut {
+                        red: Xy {
+                            x: gamut.red.x,
+                            y: gamut.red.y,
+                        },
+                        green: Xy {
+                            x: gamut.green.x,
+                            y: gamut.green.y,
+                        },
+                        blue: Xy {
+                            x: gamut.blue.x,
+                            y: gamut.blue.y,
+                        },
+                    }),
             });
 
             let mqtt_device = builder.build().unwrap();
@@ -81,8 +121,9 @@ pub fn init_state_to_mqtt_devices(init_state: &HueState) -> HashMap<String, Mqtt
             ));
 
             if let Some(button_event) = &button.button {
-                builder.sensor_value(button_event.is_pressed().to_string());
+                builder.sensor_value(SensorValue::Button(button_event.is_pressed()));
                 builder.updated(button_event.button_report.updated.clone());
+                builder.event_updated(button_event.button_report.updated.clone());
             }
 
             let mqtt_device = builder.build().unwrap();
@@ -102,7 +143,7 @@ pub fn init_state_to_mqtt_devices(init_state: &HueState) -> HashMap<String, Mqtt
                 .name(device.metadata.name.clone());
 
             if let Some(motion_event) = &motion.motion {
-                builder.sensor_value(motion_event.motion.to_string());
+                builder.sensor_value(SensorValue::Motion(motion_event.motion));
             }
 
             let mqtt_device = builder.build().unwrap();
@@ -124,7 +165,7 @@ pub fn init_state_to_mqtt_devices(init_state: &HueState) -> HashMap<String, Mqtt
             ));
 
             if let Some(temperature_event) = &temperature.temperature {
-                builder.sensor_value(temperature_event.temperature.to_string());
+                builder.sensor_value(SensorValue::Temperature(temperature_event.temperature));
             }
 
             let mqtt_device = builder.build().unwrap();
@@ -146,7 +187,36 @@ pub fn init_state_to_mqtt_devices(init_state: &HueState) -> HashMap<String, Mqtt
             ));
 
             if let Some(light_level_event) = &light_level.light {
-                builder.sensor_value(light_level_event.light_level.to_string());
+                builder.sensor_value(SensorValue::LightLevel(
+                    light_level_event.light_level as f64,
+                ));
+            }
+
+            let mqtt_device = builder.build().unwrap();
+
+            mqtt_devices.insert(mqtt_device.id.clone(), mqtt_device);
+        }
+    }
+
+    for grouped_light in init_state.grouped_lights.values() {
+        // Grouped lights are owned by either a room or a zone. Ignore the
+        // grouped light containing all lights of the bridge.
+        let name = group_name(init_state, &grouped_light.owner);
+
+        if let Some(name) = name {
+            let mut builder = MqttDeviceBuilder::default();
+
+            builder
+                .id(grouped_light.id.clone())
+                .name(name)
+                .is_group(true);
+
+            if let Some(on) = &grouped_light.on {
+                builder.power(on.on);
+            }
+
+            if let Some(dimming) = &grouped_light.dimming {
+                builder.brightness(dimming.brightness / 100.0);
             }
 
             let mqtt_device = builder.build().unwrap();
@@ -158,6 +228,58 @@ pub fn init_state_to_mqtt_devices(init_state: &HueState) -> HashMap<String, Mqtt
     mqtt_devices
 }
 
+pub fn init_state_to_mqtt_scenes(init_state: &HueState) -> HashMap<String, MqttScene> {
+    init_state
+        .scenes
+        .values()
+        .map(|scene| {
+            let mqtt_scene = MqttScene {
+                id: scene.id.clone(),
+                name: scene.metadata.name.clone(),
+                group: scene.group.rid.clone(),
+                group_name: group_name(init_state, &scene.group),
+                status: scene.status.as_ref().map(|status| status.active.clone()),
+            };
+
+            (mqtt_scene.id.clone(), mqtt_scene)
+        })
+        .collect()
+}
+
+/// Returns the battery state of battery powered devices, keyed by device_power
+/// resource ID.
+pub fn init_state_to_mqtt_batteries(init_state: &HueState) -> HashMap<String, MqttBattery> {
+    init_state
+        .device_power
+        .values()
+        .filter_map(|device_power| {
+            let device = init_state.devices.get(&device_power.owner.rid)?;
+
+            let mqtt_battery = MqttBattery {
+                id: device.id.clone(),
+                name: device.metadata.name.clone(),
+                battery_level: device_power.power_state.battery_level,
+                battery_state: device_power.power_state.battery_state.clone(),
+            };
+
+            Some((device_power.id.clone(), mqtt_battery))
+        })
+        .collect()
+}
+
+/// Returns the names of relative rotary resources, keyed by resource ID.
+pub fn init_state_to_rotary_names(init_state: &HueState) -> HashMap<String, String> {
+    init_state
+        .relative_rotary
+        .values()
+        .filter_map(|relative_rotary| {
+            let device = init_state.devices.get(&relative_rotary.owner.rid)?;
+
+            Some((relative_rotary.id.clone(), device.metadata.name.clone()))
+        })
+        .collect()
+}
+
 pub async fn publish_hue_state(
     settings: &Settings,
     mqtt_client: &MqttClient,
@@ -167,20 +289,29 @@ pub async fn publish_hue_state(
 
     // Publish initial state of each discovered device to MQTT
     for mqtt_device in mqtt_devices.values() {
-        let topic_template = if mqtt_device.sensor_value.is_some() {
-            &settings.mqtt.sensor_topic
-        } else {
-            &settings.mqtt.light_topic
-        };
+        publish_mqtt_device(mqtt_client, settings, mqtt_device).await?;
+    }
 
-        let topic = topic_template.replace("{id}", &mqtt_device.id);
+    let mqtt_scenes = init_state_to_mqtt_scenes(hue_state);
 
-        let json = serde_json::to_string(&mqtt_device)?;
+    for mqtt_scene in mqtt_scenes.values() {
+        publish_mqtt_scene(mqtt_client, settings, mqtt_scene).await?;
+    }
+
+    let mqtt_batteries = init_state_to_mqtt_batteries(hue_state);
+
+    for mqtt_battery in mqtt_batteries.values() {
+        publish_mqtt_battery(mqtt_client, settings, mqtt_battery).await?;
+    }
 
-        mqtt_client
-            .client
-            .publish(topic, QoS::AtLeastOnce, true, json)
-            .await?;
+    for zigbee_connectivity in hue_state.zigbee_connectivity.values() {
+        publish_device_availability(
+            mqtt_client,
+            settings,
+            &zigbee_connectivity.owner.rid,
+            zigbee_connectivity.is_available(),
+        )
+        .await?;
     }
 
     Ok(())
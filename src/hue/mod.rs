@@ -1,5 +1,9 @@
+pub mod button_gestures;
+pub mod discovery;
 pub mod event_data;
 pub mod events;
 pub mod init_state;
+pub mod pending_commands;
 pub mod polling;
 pub mod rest;
+pub mod sync_state;
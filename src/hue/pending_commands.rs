@@ -0,0 +1,328 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use color_eyre::Result;
+use serde::Serialize;
+use tokio::{
+    sync::RwLock,
+    time::{interval, Instant},
+};
+
+use super::events::EventsourceState;
+use crate::{
+    metrics::metrics,
+    mqtt::{
+        events::MqttCommand,
+        mqtt_device::{Ct, DeviceColor, MqttDevice},
+    },
+    protocols::mqtt::MqttClient,
+    settings::Settings,
+};
+
+#[derive(Clone, Debug)]
+struct PendingCommand {
+    mqtt_device: MqttDevice,
+    retries: u32,
+    sent_at: Instant,
+}
+
+/// Light commands that have been successfully sent to the Hue bridge, but not
+/// yet acknowledged by the light through the eventsource API.
+#[derive(Clone, Default)]
+pub struct PendingCommands {
+    commands: Arc<RwLock<HashMap<String, PendingCommand>>>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct CommandError<'a> {
+    id: &'a str,
+    name: &'a str,
+    error: String,
+    command: &'a MqttDevice,
+}
+
+/// Returns whether the reported light state matches all fields set in the
+/// requested state.
+fn has_converged(requested: &MqttDevice, reported: &MqttDevice) -> bool {
+    if let Some(power) = requested.power {
+        if reported.power != Some(power) {
+            return false;
+        }
+
+        // Lights that are turned off won't report changes to other fields
+        if !power {
+            return true;
+        }
+    }
+
+    if let Some(brightness) = requested.brightness {
+        match reported.brightness {
+            Some(reported) if (reported - brightness).abs() <= 0.01 => {}
+            _ => return false,
+        }
+    }
+
+    match (&requested.color, &reported.color) {
+        (None, _) => true,
+        (Some(DeviceColor::Xy(requested)), Some(DeviceColor::Xy(reported))) => {
+            (requested.x - reported.x).abs() <= 0.01 && (requested.y - reported.y).abs() <= 0.01
+        }
+        (
+            Some(DeviceColor::Ct(Ct { ct: requested })),
+            Some(DeviceColor::Ct(Ct { ct: reported })),
+        ) => {
+            // Compare in mireds, as that is what the light reports
+            let requested = 1_000_000.0 / *requested as f32;
+            let reported = 1_000_000.0 / *reported as f32;
+
+            (requested - reported).abs() <= 2.0
+        }
+        _ => false,
+    }
+}
+
+impl PendingCommands {
+    /// Starts tracking a command that was just sent to the Hue bridge.
+    pub async fn track(&self, mqtt_device: &MqttDevice) {
+        // Grouped light updates only reflect the aggregate state of the group,
+        // so there is no way to tell whether every light has been updated.
+        if mqtt_device.is_group {
+            return;
+        }
+
+        // Relative changes must not be applied again when retrying
+        let mqtt_device = &MqttDevice {
+            brightness_step: None,
+            ct_step: None,
+            ..mqtt_device.clone()
+        };
+
+        let mut commands = self.commands.write().await;
+
+        // Retried commands keep their retry count, new commands replace any
+        // previous command for the same light.
+        let retries = match commands.get(&mqtt_device.id) {
+            Some(pending) if &pending.mqtt_device == mqtt_device => pending.retries,
+            _ => 0,
+        };
+
+        commands.insert(
+            mqtt_device.id.clone(),
+            PendingCommand {
+                mqtt_device: mqtt_device.clone(),
+                retries,
+                sent_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Stops tracking the pending command for a light once its reported state
+    /// matches the requested state.
+    pub async fn acknowledge(&self, reported: &MqttDevice) {
+        let mut commands = self.commands.write().await;
+
+        if let Some(pending) = commands.get(&reported.id) {
+            if has_converged(&pending.mqtt_device, reported) {
+                commands.remove(&reported.id);
+            }
+        }
+    }
+}
+
+async fn publish_command_error(
+    mqtt_client: &MqttClient,
+    settings: &Settings,
+    mqtt_device: &MqttDevice,
+    retries: u32,
+) -> Result<()> {
+    let topic = settings
+        .mqtt
+        .light_topic_error
+        .replace("{id}", &mqtt_device.id);
+
+    let error = CommandError {
+        id: &mqtt_device.id,
+        name: &mqtt_device.name,
+        error: format!("Light did not reach requested state after {retries} retries"),
+        command: mqtt_device,
+    };
+
+    let json = serde_json::to_string(&error)?;
+
+    mqtt_client
+        .client
+        .publish(topic, rumqttc::QoS::AtLeastOnce, false, json)
+        .await?;
+
+    Ok(())
+}
+
+/// Re-sends light commands that have not been acknowledged within
+/// `command_timeout_ms`, and publishes an error to the light's error topic
+/// once `command_retries` retries have been exhausted.
+pub fn start_pending_commands_loop(
+    settings: &Settings,
+    mqtt_client: &MqttClient,
+    state: &EventsourceState,
+) {
+    let settings = settings.clone();
+    let mqtt_client = mqtt_client.clone();
+    let mqtt_devices = state.mqtt_devices.clone();
+    let pending_commands = state.pending_commands.clone();
+
+    let timeout = Duration::from_millis(settings.hue_bridge.command_timeout_ms);
+
+    tokio::spawn(async move {
+        let mut interval = interval(Duration::from_millis(250));
+
+        loop {
+            interval.tick().await;
+
+            let mut retry = vec![];
+            let mut failed = vec![];
+
+            {
+                let mqtt_devices = mqtt_devices.read().await;
+                let mut commands = pending_commands.commands.write().await;
+
+                commands.retain(|id, pending| {
+                    // The bridge sends no events for commands that don't
+                    // change anything, so also compare against the last known
+                    // state of the light
+                    let converged = mqtt_devices
+                        .get(id)
+                        .map(|reported| has_converged(&pending.mqtt_device, reported))
+                        .unwrap_or(false);
+
+                    if converged {
+                        false
+                    } else if pending.sent_at.elapsed() < timeout {
+                        true
+                    } else if pending.retries < settings.hue_bridge.command_retries {
+                        pending.retries += 1;
+                        pending.sent_at = Instant::now();
+                        retry.push(pending.mqtt_device.clone());
+                        true
+                    } else {
+                        failed.push(pending.clone());
+                        false
+                    }
+                });
+            }
+
+            for mqtt_device in retry {
+                debug!("Retrying command for light {}", mqtt_device.name);
+
+                // Retries are skipped if a newer command for the same light is
+                // already waiting to be sent
+                mqtt_client
+                    .command_queue
+                    .push_retry(MqttCommand::Device(mqtt_device))
+                    .await;
+            }
+
+            for pending in failed {
+                let result = publish_command_error(
+                    &mqtt_client,
+                    &settings,
+                    &pending.mqtt_device,
+                    pending.retries,
+                )
+                .await;
+
+                if let Err(e) = result {
+                    eprintln!("Error publishing command error: {e:?}");
+                    metrics().mqtt_publish_failures.inc();
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(json: &str) -> MqttDevice {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn off_lights_ignore_other_fields() {
+        let requested = device(r#"{"id": "1", "name": "Lamp", "power": false, "brightness": 0.5}"#);
+        let reported = device(r#"{"id": "1", "name": "Lamp", "power": false, "brightness": 0.1}"#);
+
+        assert!(has_converged(&requested, &reported));
+    }
+
+    #[test]
+    fn power_must_match() {
+        let requested = device(r#"{"id": "1", "name": "Lamp", "power": true}"#);
+        let reported = device(r#"{"id": "1", "name": "Lamp", "power": false}"#);
+
+        assert!(!has_converged(&requested, &reported));
+    }
+
+    #[test]
+    fn brightness_within_tolerance() {
+        let requested = device(r#"{"id": "1", "name": "Lamp", "brightness": 0.5}"#);
+
+        assert!(has_converged(
+            &requested,
+            &device(r#"{"id": "1", "name": "Lamp", "brightness": 0.505}"#)
+        ));
+        assert!(!has_converged(
+            &requested,
+            &device(r#"{"id": "1", "name": "Lamp", "brightness": 0.52}"#)
+        ));
+    }
+
+    #[test]
+    fn xy_within_tolerance() {
+        let requested = device(r#"{"id": "1", "name": "Lamp", "color": {"x": 0.3, "y": 0.3}}"#);
+
+        assert!(has_converged(
+            &requested,
+            &device(r#"{"id": "1", "name": "Lamp", "color": {"x": 0.305, "y": 0.295}}"#)
+        ));
+        assert!(!has_converged(
+            &requested,
+            &device(r#"{"id": "1", "name": "Lamp", "color": {"x": 0.3, "y": 0.32}}"#)
+        ));
+    }
+
+    #[test]
+    fn ct_within_mirek_tolerance() {
+        // 2700 K is 370.4 mirek
+        let requested = device(r#"{"id": "1", "name": "Lamp", "color": {"ct": 2700}}"#);
+
+        // 369 mirek
+        assert!(has_converged(
+            &requested,
+            &device(r#"{"id": "1", "name": "Lamp", "color": {"ct": 2710}}"#)
+        ));
+
+        // 366.3 mirek
+        assert!(!has_converged(
+            &requested,
+            &device(r#"{"id": "1", "name": "Lamp", "color": {"ct": 2730}}"#)
+        ));
+    }
+
+    #[test]
+    fn xy_and_ct_do_not_match() {
+        let requested = device(r#"{"id": "1", "name": "Lamp", "color": {"ct": 2700}}"#);
+        let reported = device(r#"{"id": "1", "name": "Lamp", "color": {"x": 0.46, "y": 0.41}}"#);
+
+        assert!(!has_converged(&requested, &reported));
+        assert!(!has_converged(&reported, &requested));
+    }
+
+    #[test]
+    fn unset_color_always_matches() {
+        let requested = device(r#"{"id": "1", "name": "Lamp", "power": true}"#);
+        let reported =
+            device(r#"{"id": "1", "name": "Lamp", "power": true, "color": {"ct": 2700}}"#);
+
+        assert!(has_converged(&requested, &reported));
+    }
+}
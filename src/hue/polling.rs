@@ -1,76 +1,147 @@
-use std::{collections::HashMap, sync::Arc};
+use std::collections::HashMap;
 
 use super::{
+    discovery::note_bridge_failure,
+    events::EventsourceState,
     init_state::publish_hue_state,
-    rest::{button::get_hue_buttons, get_hue_state},
+    rest::{
+        button::{get_hue_buttons, ButtonData},
+        get_hue_state,
+    },
+    sync_state::sync_hue_state,
 };
 use crate::{
-    mqtt::mqtt_device::{publish_mqtt_device, MqttDevice},
+    metrics::metrics,
+    mqtt::{
+        availability::publish_status,
+        homeassistant::publish_homeassistant_discovery,
+        mqtt_button_event::{publish_mqtt_button_event, MqttButtonEvent},
+        mqtt_device::{publish_mqtt_device, MqttDevice, SensorValue},
+    },
     protocols::{https::HyperHttpsClient, mqtt::MqttClient},
     settings::Settings,
 };
 use color_eyre::Result;
-use tokio::sync::RwLock;
 
 /// Periodically poll for hue state and publish to MQTT.
 ///
 /// The zigbee network may drop state change messages, and we will never know
 /// about that happening through only the eventsource API.
 ///
-/// TODO:
-/// Another perhaps better idea for solving this problem would be:
-///
-/// - Every time we request a light state change for a specific device, keep
-/// listening for events from the eventsource api
-/// - If an "acknowledgement" of the state change arrives from the device, we
-/// know that the bulb has been set to the correct state and we don't need to do
-/// anything else
-/// - If no event is received within say 2 seconds, re-send the state change
-/// request
+/// Light commands sent by us are re-sent until the light acknowledges them
+/// (see `pending_commands`), so this mainly serves as a safety net for state
+/// changes made by other Hue bridge clients, and for picking up resources that
+/// are added to or removed from the bridge. Polling happens immediately when
+/// the eventsource API reports such a change.
 pub fn start_hue_state_poll_loop(
     settings: &Settings,
     https_client: &HyperHttpsClient,
     mqtt_client: &MqttClient,
+    state: &EventsourceState,
 ) {
     let settings = settings.clone();
     let https_client = https_client.clone();
     let mqtt_client = mqtt_client.clone();
+    let state = state.clone();
 
     tokio::spawn(async move {
+        // Home Assistant discovery configs published so far, keyed by topic
+        let mut discovery_configs = HashMap::new();
+
+        // Consecutive failed polls
+        let mut failures = 0;
+
         loop {
-            let state = get_hue_state(&settings, &https_client).await;
+            let timer = metrics().poll_duration.start_timer();
+            let result = poll_and_publish_hue_state(
+                &settings,
+                &https_client,
+                &mqtt_client,
+                &state,
+                &mut discovery_configs,
+            )
+            .await;
+            timer.observe_duration();
+
+            *state.health.bridge_reachable.write().await = result.is_ok();
+
+            // The bridge may have been assigned a new address
+            if result.is_ok() {
+                failures = 0;
+            } else {
+                note_bridge_failure(&mut failures).await;
+            }
 
-            let result = match state {
-                Ok(state) => publish_hue_state(&settings, &mqtt_client, &state).await,
-                Err(e) => Err(e),
-            };
+            // Let MQTT clients know whether we are able to reach the bridge
+            let status_result = publish_status(&mqtt_client, &settings, result.is_ok()).await;
 
-            if let Err(e) = result {
+            if let Err(e) = result.and(status_result) {
                 eprintln!("{:?}", e);
             };
 
-            tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+            tokio::select! {
+                _ = tokio::time::sleep(std::time::Duration::from_secs(10)) => {}
+                _ = state.resync.notified() => {}
+            }
         }
     });
 }
 
+async fn poll_and_publish_hue_state(
+    settings: &Settings,
+    https_client: &HyperHttpsClient,
+    mqtt_client: &MqttClient,
+    state: &EventsourceState,
+    discovery_configs: &mut HashMap<String, String>,
+) -> Result<()> {
+    let hue_state = get_hue_state(settings, https_client).await?;
+
+    sync_hue_state(settings, mqtt_client, state, &hue_state).await?;
+    publish_hue_state(settings, mqtt_client, &hue_state).await?;
+    publish_homeassistant_discovery(settings, mqtt_client, &hue_state, discovery_configs).await?;
+
+    Ok(())
+}
+
 /// This function polls the Hue bridge's Button resource API and publishes
 /// detected button state changes to MQTT.
 pub async fn poll_hue_buttons(
     settings: &Settings,
     mqtt_client: &MqttClient,
     https_client: &HyperHttpsClient,
-    mqtt_devices: &Arc<RwLock<HashMap<String, MqttDevice>>>,
+    state: &EventsourceState,
 ) -> Result<()> {
     let poll_result = get_hue_buttons(settings, https_client).await?;
 
+    handle_hue_button_poll(settings, mqtt_client, state, poll_result).await
+}
+
+/// Publishes button state changes detected from the result of polling the Hue
+/// bridge's Button resource API.
+pub async fn handle_hue_button_poll(
+    settings: &Settings,
+    mqtt_client: &MqttClient,
+    state: &EventsourceState,
+    poll_result: Vec<ButtonData>,
+) -> Result<()> {
+    let mut button_events: Vec<MqttButtonEvent> = vec![];
+
     // Collect changed mqtt_devices
     let changed_mqtt_devices: Vec<MqttDevice> = {
-        let mut mqtt_devices = mqtt_devices.write().await;
+        let mut mqtt_devices = state.mqtt_devices.write().await;
         let mut result = vec![];
 
         for button in poll_result {
-            let mqtt_device = mqtt_devices.get_mut(&button.id);
+            let mut mqtt_device = mqtt_devices.get_mut(&button.id);
+
+            if let (Some(mqtt_device), Some(button)) = (mqtt_device.as_deref_mut(), &button.button)
+            {
+                if let Some(button_event) =
+                    MqttButtonEvent::from_report(mqtt_device, &button.button_report)
+                {
+                    button_events.push(button_event);
+                }
+            }
 
             // Ignore already seen button reports
             if let (Some(updated), Some(button)) = (
@@ -92,33 +163,33 @@ pub async fn poll_hue_buttons(
             // Check if button state matches previously seen sensor value
             if let (Some(mqtt_device), Some(button)) = (mqtt_device, &button.button) {
                 match (
-                    mqtt_device.sensor_value.as_deref(),
+                    mqtt_device.sensor_value,
                     button.is_pressed(),
                     mqtt_device.updated.as_ref(),
                 ) {
-                    (Some("false"), true, _) => {
-                        mqtt_device.sensor_value = Some(true.to_string());
+                    (Some(SensorValue::Button(false)), true, _) => {
+                        mqtt_device.sensor_value = Some(SensorValue::Button(true));
                         result.push(mqtt_device.clone());
                     }
-                    (Some("true"), false, _) => {
-                        mqtt_device.sensor_value = Some(false.to_string());
+                    (Some(SensorValue::Button(true)), false, _) => {
+                        mqtt_device.sensor_value = Some(SensorValue::Button(false));
                         result.push(mqtt_device.clone());
                     }
-                    (Some("false"), false, Some(updated)) => {
+                    (Some(SensorValue::Button(false)), false, Some(updated)) => {
                         // We seem to have missed a false -> true -> false transition, let's fake a sensor_value of "true"
                         if updated != &button.button_report.updated {
-                            mqtt_device.sensor_value = Some(true.to_string());
+                            mqtt_device.sensor_value = Some(SensorValue::Button(true));
                             result.push(mqtt_device.clone());
-                            mqtt_device.sensor_value = Some(false.to_string());
+                            mqtt_device.sensor_value = Some(SensorValue::Button(false));
                             result.push(mqtt_device.clone());
                         }
                     }
-                    (Some("true"), true, Some(updated)) => {
+                    (Some(SensorValue::Button(true)), true, Some(updated)) => {
                         // We seem to have missed a true -> false -> true transition, let's fake a sensor_value of "false"
                         if updated != &button.button_report.updated {
-                            mqtt_device.sensor_value = Some(false.to_string());
+                            mqtt_device.sensor_value = Some(SensorValue::Button(false));
                             result.push(mqtt_device.clone());
-                            mqtt_device.sensor_value = Some(true.to_string());
+                            mqtt_device.sensor_value = Some(SensorValue::Button(true));
                             result.push(mqtt_device.clone());
                         }
                     }
@@ -133,6 +204,18 @@ pub async fn poll_hue_buttons(
         result
     };
 
+    for button_event in button_events {
+        let publish_result = publish_mqtt_button_event(mqtt_client, settings, &button_event).await;
+
+        if let Err(e) = publish_result {
+            eprintln!("{:?}", e);
+        }
+    }
+
+    for mqtt_device in &changed_mqtt_devices {
+        state.button_gestures.handle(mqtt_device).await;
+    }
+
     // Publish changed mqtt_devices to the broker
     for mqtt_device in changed_mqtt_devices {
         let publish_result = publish_mqtt_device(mqtt_client, settings, &mqtt_device).await;
@@ -0,0 +1,46 @@
+use color_eyre::Result;
+use serde::Deserialize;
+
+use crate::{
+    protocols::https::{mk_get_request, HyperHttpsClient},
+    settings::Settings,
+};
+
+use super::common::Owner;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PowerStateData {
+    /// Battery level in percent (0 - 100)
+    pub battery_level: Option<u8>,
+
+    /// One of "normal", "low" or "critical"
+    pub battery_state: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct DevicePowerData {
+    pub id: String,
+    pub id_v1: Option<String>,
+    pub owner: Owner,
+    pub power_state: PowerStateData,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct DevicePowerResponse {
+    data: Vec<DevicePowerData>,
+}
+
+pub async fn get_hue_device_power(
+    settings: &Settings,
+    client: &HyperHttpsClient,
+) -> Result<Vec<DevicePowerData>> {
+    let uri = format!(
+        "https://{}/clip/v2/resource/device_power",
+        settings.hue_bridge.addr
+    )
+    .parse()?;
+
+    let response: DevicePowerResponse = mk_get_request(client, settings, &uri).await?;
+
+    Ok(response.data)
+}
@@ -0,0 +1,60 @@
+use color_eyre::Result;
+use serde::Deserialize;
+
+use crate::{
+    mqtt::mqtt_device::MqttDevice,
+    protocols::https::{mk_get_request, mk_put_request, HyperHttpsClient},
+    settings::Settings,
+};
+
+use super::{
+    common::Owner,
+    light::{DimmingData, LightRequest, OnData, PutResponse},
+};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct GroupedLightData {
+    pub id: String,
+    pub id_v1: Option<String>,
+    pub owner: Owner,
+    pub on: Option<OnData>,
+    pub dimming: Option<DimmingData>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct GroupedLightResponse {
+    data: Vec<GroupedLightData>,
+}
+
+pub async fn get_hue_grouped_lights(
+    settings: &Settings,
+    client: &HyperHttpsClient,
+) -> Result<Vec<GroupedLightData>> {
+    let uri = format!(
+        "https://{}/clip/v2/resource/grouped_light",
+        settings.hue_bridge.addr
+    )
+    .parse()?;
+
+    let response: GroupedLightResponse = mk_get_request(client, settings, &uri).await?;
+
+    Ok(response.data)
+}
+
+pub async fn put_hue_grouped_light(
+    settings: &Settings,
+    client: &HyperHttpsClient,
+    mqtt_device: &MqttDevice,
+) -> Result<PutResponse> {
+    let uri = format!(
+        "https://{}/clip/v2/resource/grouped_light/{}",
+        settings.hue_bridge.addr, mqtt_device.id
+    )
+    .parse()?;
+
+    let body = LightRequest::from(mqtt_device);
+
+    let response: PutResponse = mk_put_request(client, settings, &uri, &body).await?;
+
+    Ok(response)
+}
@@ -37,9 +37,23 @@ pub struct ColorTemperatureData {
     pub mirek_schema: Option<MirekSchema>,
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GamutData {
+    pub red: XyData,
+    pub green: XyData,
+    pub blue: XyData,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct ColorData {
     pub xy: XyData,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gamut: Option<GamutData>,
+
+    /// One of "A", "B", "C" or "other"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gamut_type: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -86,16 +100,41 @@ struct DynamicsData {
 }
 
 #[derive(Serialize, Debug, Clone)]
-struct LightRequest {
+#[serde(rename_all = "snake_case")]
+enum DeltaAction {
+    Up,
+    Down,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct DimmingDeltaData {
+    action: DeltaAction,
+    brightness_delta: f32,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct ColorTemperatureDeltaData {
+    action: DeltaAction,
+    mirek_delta: u16,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct LightRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     on: Option<OnData>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     dimming: Option<DimmingData>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dimming_delta: Option<DimmingDeltaData>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     color_temperature: Option<ColorTemperatureData>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color_temperature_delta: Option<ColorTemperatureDeltaData>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     color: Option<ColorData>,
 
@@ -103,6 +142,65 @@ struct LightRequest {
     dynamics: Option<DynamicsData>,
 }
 
+impl From<&MqttDevice> for LightRequest {
+    fn from(mqtt_device: &MqttDevice) -> Self {
+        LightRequest {
+            on: mqtt_device.power.map(|power| OnData { on: power }),
+            dimming: mqtt_device.brightness.map(|brightness| DimmingData {
+                brightness: brightness * 100.0,
+            }),
+            // Absolute values take precedence over relative changes
+            dimming_delta: mqtt_device
+                .brightness_step
+                .filter(|_| mqtt_device.brightness.is_none())
+                .map(|step| DimmingDeltaData {
+                    action: if step < 0.0 {
+                        DeltaAction::Down
+                    } else {
+                        DeltaAction::Up
+                    },
+                    brightness_delta: (step.abs() * 100.0).min(100.0),
+                }),
+            color_temperature: mqtt_device.color.as_ref().and_then(|color| {
+                if let DeviceColor::Ct(Ct { ct }) = color {
+                    Some(ColorTemperatureData {
+                        mirek: Some(1_000_000.0 / *ct as f32),
+                        mirek_schema: None,
+                    })
+                } else {
+                    None
+                }
+            }),
+            color_temperature_delta: mqtt_device
+                .ct_step
+                .filter(|_| mqtt_device.color.is_none())
+                .map(|step| ColorTemperatureDeltaData {
+                    // Higher mirek values are warmer
+                    action: if step < 0 {
+                        DeltaAction::Down
+                    } else {
+                        DeltaAction::Up
+                    },
+                    mirek_delta: step.unsigned_abs().min(347) as u16,
+                }),
+            color: mqtt_device.color.as_ref().and_then(|color| {
+                if let DeviceColor::Xy(Xy { x, y }) = color {
+                    Some(ColorData {
+                        xy: XyData { x: *x, y: *y },
+                        gamut: None,
+                        gamut_type: None,
+                    })
+                } else {
+                    None
+                }
+            }),
+            dynamics: mqtt_device.transition_ms.map(|transition_ms| DynamicsData {
+                duration: transition_ms as u32,
+            }),
+        }
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct PutError {
     pub description: String,
@@ -131,34 +229,7 @@ pub async fn put_hue_light(
     )
     .parse()?;
 
-    let body = LightRequest {
-        on: mqtt_device.power.map(|power| OnData { on: power }),
-        dimming: mqtt_device.brightness.map(|brightness| DimmingData {
-            brightness: brightness * 100.0,
-        }),
-        color_temperature: mqtt_device.color.as_ref().and_then(|color| {
-            if let DeviceColor::Ct(Ct { ct }) = color {
-                Some(ColorTemperatureData {
-                    mirek: Some(1_000_000.0 / *ct as f32),
-                    mirek_schema: None,
-                })
-            } else {
-                None
-            }
-        }),
-        color: mqtt_device.color.as_ref().and_then(|color| {
-            if let DeviceColor::Xy(Xy { x, y }) = color {
-                Some(ColorData {
-                    xy: XyData { x: *x, y: *y },
-                })
-            } else {
-                None
-            }
-        }),
-        dynamics: mqtt_device.transition_ms.map(|transition_ms| DynamicsData {
-            duration: transition_ms as u32,
-        }),
-    };
+    let body = LightRequest::from(mqtt_device);
 
     let response: PutResponse = mk_put_request(client, settings, &uri, &body).await?;
 
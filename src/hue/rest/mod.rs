@@ -3,10 +3,17 @@ use std::collections::HashMap;
 use self::{
     button::{get_hue_buttons, ButtonData, ButtonEventData, ButtonReport},
     device::{get_hue_devices, DeviceData},
+    device_power::{get_hue_device_power, DevicePowerData},
+    grouped_light::{get_hue_grouped_lights, GroupedLightData},
     light::{get_hue_lights, LightData},
     light_level::{get_hue_light_level, LightLevelData},
     motion::{get_hue_motion, MotionData},
+    relative_rotary::{get_hue_relative_rotary, RelativeRotaryData},
+    room::{get_hue_rooms, RoomData},
+    scene::{get_hue_scenes, SceneData},
     temperature::{get_hue_temperature, TemperatureData},
+    zigbee_connectivity::{get_hue_zigbee_connectivity, ZigbeeConnectivityData},
+    zone::{get_hue_zones, ZoneData},
 };
 use crate::{protocols::https::HyperHttpsClient, settings::Settings};
 use color_eyre::Result;
@@ -14,10 +21,17 @@ use color_eyre::Result;
 pub mod button;
 pub mod common;
 pub mod device;
+pub mod device_power;
+pub mod grouped_light;
 pub mod light;
 pub mod light_level;
 pub mod motion;
+pub mod relative_rotary;
+pub mod room;
+pub mod scene;
 pub mod temperature;
+pub mod zigbee_connectivity;
+pub mod zone;
 
 #[derive(Clone, Debug)]
 pub struct HueState {
@@ -27,6 +41,13 @@ pub struct HueState {
     pub motion: HashMap<String, MotionData>,
     pub temperature: HashMap<String, TemperatureData>,
     pub light_level: HashMap<String, LightLevelData>,
+    pub rooms: HashMap<String, RoomData>,
+    pub zones: HashMap<String, ZoneData>,
+    pub grouped_lights: HashMap<String, GroupedLightData>,
+    pub scenes: HashMap<String, SceneData>,
+    pub zigbee_connectivity: HashMap<String, ZigbeeConnectivityData>,
+    pub device_power: HashMap<String, DevicePowerData>,
+    pub relative_rotary: HashMap<String, RelativeRotaryData>,
 }
 
 pub async fn get_hue_state(settings: &Settings, client: &HyperHttpsClient) -> Result<HueState> {
@@ -36,6 +57,13 @@ pub async fn get_hue_state(settings: &Settings, client: &HyperHttpsClient) -> Re
     let motion = get_hue_motion(settings, client).await?;
     let temperature = get_hue_temperature(settings, client).await?;
     let light_level = get_hue_light_level(settings, client).await?;
+    let rooms = get_hue_rooms(settings, client).await?;
+    let zones = get_hue_zones(settings, client).await?;
+    let grouped_lights = get_hue_grouped_lights(settings, client).await?;
+    let scenes = get_hue_scenes(settings, client).await?;
+    let zigbee_connectivity = get_hue_zigbee_connectivity(settings, client).await?;
+    let device_power = get_hue_device_power(settings, client).await?;
+    let relative_rotary = get_hue_relative_rotary(settings, client).await?;
 
     // Fix some data quality issues
     let buttons: Vec<ButtonData> = buttons
@@ -68,6 +96,25 @@ pub async fn get_hue_state(settings: &Settings, client: &HyperHttpsClient) -> Re
     let motion = motion.into_iter().map(|x| (x.id.clone(), x)).collect();
     let temperature = temperature.into_iter().map(|x| (x.id.clone(), x)).collect();
     let light_level = light_level.into_iter().map(|x| (x.id.clone(), x)).collect();
+    let rooms = rooms.into_iter().map(|x| (x.id.clone(), x)).collect();
+    let zones = zones.into_iter().map(|x| (x.id.clone(), x)).collect();
+    let grouped_lights = grouped_lights
+        .into_iter()
+        .map(|x| (x.id.clone(), x))
+        .collect();
+    let scenes = scenes.into_iter().map(|x| (x.id.clone(), x)).collect();
+    let zigbee_connectivity = zigbee_connectivity
+        .into_iter()
+        .map(|x| (x.id.clone(), x))
+        .collect();
+    let device_power = device_power
+        .into_iter()
+        .map(|x| (x.id.clone(), x))
+        .collect();
+    let relative_rotary = relative_rotary
+        .into_iter()
+        .map(|x| (x.id.clone(), x))
+        .collect();
 
     Ok(HueState {
         devices,
@@ -76,5 +123,12 @@ pub async fn get_hue_state(settings: &Settings, client: &HyperHttpsClient) -> Re
         motion,
         temperature,
         light_level,
+        rooms,
+        zones,
+        grouped_lights,
+        scenes,
+        zigbee_connectivity,
+        device_power,
+        relative_rotary,
     })
 }
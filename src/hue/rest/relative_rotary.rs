@@ -0,0 +1,69 @@
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    protocols::https::{mk_get_request, HyperHttpsClient},
+    settings::Settings,
+};
+
+use super::common::Owner;
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct RotationData {
+    /// One of "clock_wise" or "counter_clock_wise"
+    pub direction: String,
+
+    /// Amount of rotation since the previous event
+    pub steps: u32,
+
+    /// Duration of the rotation since the start of the turn, in ms
+    pub duration: u32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RotaryEventData {
+    /// One of "start" or "repeat"
+    pub action: String,
+    pub rotation: RotationData,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RotaryReport {
+    pub updated: String,
+    pub action: String,
+    pub rotation: RotationData,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RelativeRotaryEventData {
+    pub last_event: Option<RotaryEventData>,
+    pub rotary_report: Option<RotaryReport>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RelativeRotaryData {
+    pub id: String,
+    pub id_v1: Option<String>,
+    pub owner: Owner,
+    pub relative_rotary: Option<RelativeRotaryEventData>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct RelativeRotaryResponse {
+    data: Vec<RelativeRotaryData>,
+}
+
+pub async fn get_hue_relative_rotary(
+    settings: &Settings,
+    client: &HyperHttpsClient,
+) -> Result<Vec<RelativeRotaryData>> {
+    let uri = format!(
+        "https://{}/clip/v2/resource/relative_rotary",
+        settings.hue_bridge.addr
+    )
+    .parse()?;
+
+    let response: RelativeRotaryResponse = mk_get_request(client, settings, &uri).await?;
+
+    Ok(response.data)
+}
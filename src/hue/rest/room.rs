@@ -0,0 +1,36 @@
+use color_eyre::Result;
+use serde::Deserialize;
+
+use crate::{
+    protocols::https::{mk_get_request, HyperHttpsClient},
+    settings::Settings,
+};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RoomMetadata {
+    pub name: String,
+    pub archetype: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RoomData {
+    pub id: String,
+    pub id_v1: Option<String>,
+    pub metadata: RoomMetadata,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct RoomResponse {
+    data: Vec<RoomData>,
+}
+
+pub async fn get_hue_rooms(
+    settings: &Settings,
+    client: &HyperHttpsClient,
+) -> Result<Vec<RoomData>> {
+    let uri = format!("https://{}/clip/v2/resource/room", settings.hue_bridge.addr).parse()?;
+
+    let response: RoomResponse = mk_get_request(client, settings, &uri).await?;
+
+    Ok(response.data)
+}
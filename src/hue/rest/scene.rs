@@ -0,0 +1,99 @@
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    protocols::https::{mk_get_request, mk_put_request, HyperHttpsClient},
+    settings::Settings,
+};
+
+use super::{
+    common::Owner,
+    light::{DimmingData, PutResponse},
+};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SceneMetadata {
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SceneStatus {
+    /// One of "inactive", "static" or "dynamic_palette"
+    pub active: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SceneData {
+    pub id: String,
+    pub id_v1: Option<String>,
+    pub metadata: SceneMetadata,
+
+    /// Room or zone that the scene belongs to
+    pub group: Owner,
+    pub status: Option<SceneStatus>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct SceneResponse {
+    data: Vec<SceneData>,
+}
+
+pub async fn get_hue_scenes(
+    settings: &Settings,
+    client: &HyperHttpsClient,
+) -> Result<Vec<SceneData>> {
+    let uri = format!(
+        "https://{}/clip/v2/resource/scene",
+        settings.hue_bridge.addr
+    )
+    .parse()?;
+
+    let response: SceneResponse = mk_get_request(client, settings, &uri).await?;
+
+    Ok(response.data)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SceneRecallAction {
+    #[default]
+    Active,
+    DynamicPalette,
+    Static,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SceneRecall {
+    pub action: SceneRecallAction,
+
+    /// Transition time measured in ms
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dimming: Option<DimmingData>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct SceneRequest {
+    recall: SceneRecall,
+}
+
+pub async fn put_hue_scene_recall(
+    settings: &Settings,
+    client: &HyperHttpsClient,
+    id: &str,
+    recall: SceneRecall,
+) -> Result<PutResponse> {
+    let uri = format!(
+        "https://{}/clip/v2/resource/scene/{}",
+        settings.hue_bridge.addr, id
+    )
+    .parse()?;
+
+    let body = SceneRequest { recall };
+
+    let response: PutResponse = mk_put_request(client, settings, &uri, &body).await?;
+
+    Ok(response)
+}
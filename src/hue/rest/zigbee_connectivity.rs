@@ -0,0 +1,51 @@
+use color_eyre::Result;
+use serde::Deserialize;
+
+use crate::{
+    protocols::https::{mk_get_request, HyperHttpsClient},
+    settings::Settings,
+};
+
+use super::common::Owner;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ZigbeeConnectivityData {
+    pub id: String,
+    pub id_v1: Option<String>,
+    pub owner: Owner,
+
+    /// One of "connected", "disconnected", "connectivity_issue" or
+    /// "unidirectional_incoming"
+    pub status: String,
+}
+
+/// Devices with any other status than "connected" can't be reliably controlled
+pub fn is_available_status(status: &str) -> bool {
+    status == "connected"
+}
+
+impl ZigbeeConnectivityData {
+    pub fn is_available(&self) -> bool {
+        is_available_status(&self.status)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct ZigbeeConnectivityResponse {
+    data: Vec<ZigbeeConnectivityData>,
+}
+
+pub async fn get_hue_zigbee_connectivity(
+    settings: &Settings,
+    client: &HyperHttpsClient,
+) -> Result<Vec<ZigbeeConnectivityData>> {
+    let uri = format!(
+        "https://{}/clip/v2/resource/zigbee_connectivity",
+        settings.hue_bridge.addr
+    )
+    .parse()?;
+
+    let response: ZigbeeConnectivityResponse = mk_get_request(client, settings, &uri).await?;
+
+    Ok(response.data)
+}
@@ -0,0 +1,36 @@
+use color_eyre::Result;
+use serde::Deserialize;
+
+use crate::{
+    protocols::https::{mk_get_request, HyperHttpsClient},
+    settings::Settings,
+};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ZoneMetadata {
+    pub name: String,
+    pub archetype: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ZoneData {
+    pub id: String,
+    pub id_v1: Option<String>,
+    pub metadata: ZoneMetadata,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct ZoneResponse {
+    data: Vec<ZoneData>,
+}
+
+pub async fn get_hue_zones(
+    settings: &Settings,
+    client: &HyperHttpsClient,
+) -> Result<Vec<ZoneData>> {
+    let uri = format!("https://{}/clip/v2/resource/zone", settings.hue_bridge.addr).parse()?;
+
+    let response: ZoneResponse = mk_get_request(client, settings, &uri).await?;
+
+    Ok(response.data)
+}
@@ -0,0 +1,105 @@
+use std::collections::{HashMap, HashSet};
+
+use color_eyre::Result;
+
+use super::{
+    events::EventsourceState,
+    init_state::{
+        init_state_to_mqtt_batteries, init_state_to_mqtt_devices, init_state_to_mqtt_scenes,
+        init_state_to_rotary_names,
+    },
+    rest::HueState,
+};
+use crate::{
+    mqtt::{
+        availability::clear_device_availability, mqtt_battery::clear_mqtt_battery,
+        mqtt_device::clear_mqtt_device, mqtt_scene::clear_mqtt_scene,
+    },
+    protocols::mqtt::MqttClient,
+    settings::Settings,
+};
+
+/// Inserts entries of `current` missing from `cache`, and removes entries of
+/// `cache` missing from `current`. Returns the removed entries.
+fn sync_cache<T: Clone>(cache: &mut HashMap<String, T>, current: HashMap<String, T>) -> Vec<T> {
+    let removed_ids: Vec<String> = cache
+        .keys()
+        .filter(|id| !current.contains_key(*id))
+        .cloned()
+        .collect();
+
+    let removed = removed_ids
+        .iter()
+        .filter_map(|id| cache.remove(id))
+        .collect();
+
+    for (id, value) in current {
+        cache.entry(id).or_insert(value);
+    }
+
+    removed
+}
+
+/// Brings the eventsource state up to date with resources that have been
+/// added to or removed from the Hue bridge, and clears the retained MQTT
+/// topics of removed resources.
+///
+/// State of resources that we already know about is left untouched, as it is
+/// kept up to date by the eventsource API.
+pub async fn sync_hue_state(
+    settings: &Settings,
+    mqtt_client: &MqttClient,
+    state: &EventsourceState,
+    hue_state: &HueState,
+) -> Result<()> {
+    let removed_devices = {
+        let mut mqtt_devices = state.mqtt_devices.write().await;
+        sync_cache(&mut mqtt_devices, init_state_to_mqtt_devices(hue_state))
+    };
+
+    for mqtt_device in removed_devices {
+        debug!("Hue resource removed: {}", mqtt_device.name);
+        clear_mqtt_device(mqtt_client, settings, &mqtt_device).await?;
+    }
+
+    let removed_scenes = {
+        let mut mqtt_scenes = state.mqtt_scenes.write().await;
+        sync_cache(&mut mqtt_scenes, init_state_to_mqtt_scenes(hue_state))
+    };
+
+    for mqtt_scene in removed_scenes {
+        debug!("Hue scene removed: {}", mqtt_scene.name);
+        clear_mqtt_scene(mqtt_client, settings, &mqtt_scene).await?;
+    }
+
+    {
+        let mut mqtt_batteries = state.mqtt_batteries.write().await;
+        sync_cache(&mut mqtt_batteries, init_state_to_mqtt_batteries(hue_state));
+    }
+
+    {
+        let mut rotary_names = state.rotary_names.write().await;
+        *rotary_names = init_state_to_rotary_names(hue_state);
+    }
+
+    // Per-device topics are cleared once the device itself is removed
+    let removed_device_ids: Vec<String> = {
+        let mut device_ids = state.device_ids.write().await;
+        let current_device_ids: HashSet<String> = hue_state.devices.keys().cloned().collect();
+        let removed = device_ids
+            .difference(&current_device_ids)
+            .cloned()
+            .collect();
+        *device_ids = current_device_ids;
+
+        removed
+    };
+
+    for device_id in removed_device_ids {
+        debug!("Hue device removed: {device_id}");
+        clear_device_availability(mqtt_client, settings, &device_id).await?;
+        clear_mqtt_battery(mqtt_client, settings, &device_id).await?;
+    }
+
+    Ok(())
+}
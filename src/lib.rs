@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+use capture::start_capture;
+use color_eyre::Result;
+use health::Health;
+use http::start_http_server;
+use hue::discovery::{note_bridge_failure, resolve_bridge_addr};
+use hue::events::start_hue_events_loop;
+use hue::pending_commands::PendingCommands;
+use hue::polling::start_hue_state_poll_loop;
+use hue::rest::get_hue_state;
+use mqtt::events::start_mqtt_events_loop;
+use protocols::https::mk_hyper_https_client;
+use protocols::mqtt::mk_mqtt_client;
+use settings::Settings;
+
+#[macro_use]
+extern crate eyre;
+
+#[macro_use]
+extern crate log;
+
+pub mod capture;
+pub mod health;
+pub mod http;
+pub mod hue;
+pub mod metrics;
+pub mod mqtt;
+pub mod pair;
+pub mod protocols;
+pub mod settings;
+
+/// Connects to the MQTT broker and Hue bridge, and starts the tasks relaying
+/// messages between them. The tasks keep running in the background once this
+/// function returns.
+pub async fn start(settings: &Settings) -> Result<()> {
+    if settings.hue_bridge.appkey.is_empty() {
+        return Err(eyre!(
+            "No Hue bridge appkey configured, run `hue-mqtt pair` to create one"
+        ));
+    }
+
+    start_capture(settings)?;
+
+    let mqtt_client = mk_mqtt_client(settings).await?;
+
+    let health = Health::new(&mqtt_client);
+    start_http_server(settings, &health)?;
+
+    // Keep retrying instead of exiting, so that readiness checks can report
+    // the bridge as unreachable in the meantime
+    let mut settings = settings.clone();
+
+    while let Err(e) = resolve_bridge_addr(&mut settings.hue_bridge).await {
+        eprintln!("Error discovering Hue bridge: {e:?}, retrying");
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+
+    let settings = &settings;
+    let https_client = mk_hyper_https_client(&settings.hue_bridge)?;
+    let mut failures = 0;
+
+    let init_state = loop {
+        match get_hue_state(settings, &https_client).await {
+            Ok(init_state) => break init_state,
+            Err(e) => {
+                eprintln!("Error fetching initial Hue bridge state: {e:?}, retrying");
+                note_bridge_failure(&mut failures).await;
+
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        }
+    };
+
+    *health.initial_state_loaded.write().await = true;
+    *health.bridge_reachable.write().await = true;
+
+    let pending_commands = PendingCommands::default();
+
+    let state = start_hue_events_loop(
+        settings,
+        &mqtt_client,
+        &https_client,
+        &init_state,
+        &pending_commands,
+        &health,
+    );
+    start_mqtt_events_loop(&mqtt_client, settings, &https_client, &state);
+    start_hue_state_poll_loop(settings, &https_client, &mqtt_client, &state);
+
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use std::sync::OnceLock;
+
+use color_eyre::Result;
+use prometheus::{
+    Encoder, Gauge, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    Opts, Registry, TextEncoder,
+};
+
+use crate::health::Health;
+
+/// Prometheus metrics describing the operation of hue-mqtt
+pub struct Metrics {
+    registry: Registry,
+
+    pub eventsource_reconnects: IntCounter,
+
+    /// Updated right before the metrics are rendered, from
+    /// `Health::prev_event_t`
+    pub last_event_age: Gauge,
+
+    /// Eventsource updates handled, labeled by Hue resource type
+    pub events_handled: IntCounterVec,
+
+    /// Eventsource updates of unsupported Hue resource types, labeled by type
+    pub updates_skipped: IntCounterVec,
+
+    /// Duration of PUT requests to the Hue bridge, labeled by Hue resource
+    /// type
+    pub put_duration: HistogramVec,
+
+    /// Failed PUT requests to the Hue bridge, labeled by Hue resource type
+    pub put_errors: IntCounterVec,
+
+    pub mqtt_publish_failures: IntCounter,
+
+    pub command_queue_depth: IntGauge,
+    pub commands_coalesced: IntCounter,
+    pub commands_dropped: IntCounter,
+    pub commands_sent: IntCounter,
+
+    pub poll_duration: Histogram,
+}
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(registry: &Registry, metric: T) -> T {
+    registry
+        .register(Box::new(metric.clone()))
+        .expect("Metric names should be unique");
+
+    metric
+}
+
+impl Metrics {
+    fn new() -> Result<Metrics> {
+        let registry = Registry::new_custom(Some("hue_mqtt".to_string()), None)?;
+
+        Ok(Metrics {
+            eventsource_reconnects: register(
+                &registry,
+                IntCounter::new(
+                    "eventsource_reconnects_total",
+                    "Number of times the Hue eventsource connection was re-established",
+                )?,
+            ),
+            last_event_age: register(
+                &registry,
+                Gauge::new(
+                    "eventsource_last_event_age_seconds",
+                    "Time since the latest Hue eventsource event was received",
+                )?,
+            ),
+            events_handled: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "eventsource_updates_total",
+                        "Number of Hue eventsource updates handled",
+                    ),
+                    &["type"],
+                )?,
+            ),
+            updates_skipped: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "eventsource_updates_skipped_total",
+                        "Number of Hue eventsource updates of unsupported resource types",
+                    ),
+                    &["type"],
+                )?,
+            ),
+            put_duration: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new(
+                        "bridge_put_duration_seconds",
+                        "Duration of PUT requests to the Hue bridge",
+                    ),
+                    &["type"],
+                )?,
+            ),
+            put_errors: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "bridge_put_errors_total",
+                        "Number of failed PUT requests to the Hue bridge",
+                    ),
+                    &["type"],
+                )?,
+            ),
+            mqtt_publish_failures: register(
+                &registry,
+                IntCounter::new(
+                    "mqtt_publish_failures_total",
+                    "Number of MQTT messages that could not be published",
+                )?,
+            ),
+            command_queue_depth: register(
+                &registry,
+                IntGauge::new(
+                    "command_queue_depth",
+                    "Number of commands waiting to be sent to the Hue bridge",
+                )?,
+            ),
+            commands_coalesced: register(
+                &registry,
+                IntCounter::new(
+                    "commands_coalesced_total",
+                    "Number of commands merged into a command already waiting in the queue",
+                )?,
+            ),
+            commands_dropped: register(
+                &registry,
+                IntCounter::new(
+                    "commands_dropped_total",
+                    "Number of commands dropped because the command queue was full",
+                )?,
+            ),
+            commands_sent: register(
+                &registry,
+                IntCounter::new(
+                    "commands_sent_total",
+                    "Number of commands successfully sent to the Hue bridge",
+                )?,
+            ),
+            poll_duration: register(
+                &registry,
+                Histogram::with_opts(HistogramOpts::new(
+                    "bridge_poll_duration_seconds",
+                    "Duration of polling and publishing the full Hue bridge state",
+                ))?,
+            ),
+            registry,
+        })
+    }
+}
+
+/// Returns the global metrics instance
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+    METRICS.get_or_init(|| Metrics::new().expect("Metrics should be valid"))
+}
+
+/// Renders all metrics in the Prometheus text format
+pub async fn render_metrics(health: &Health) -> Result<String> {
+    let metrics = metrics();
+
+    if let Some(prev_event_t) = *health.prev_event_t.read().await {
+        metrics
+            .last_event_age
+            .set(prev_event_t.elapsed().as_secs_f64());
+    }
+
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&metrics.registry.gather(), &mut buffer)?;
+
+    Ok(String::from_utf8(buffer)?)
+}
@@ -0,0 +1,85 @@
+use color_eyre::Result;
+use rumqttc::{LastWill, QoS};
+
+use crate::{protocols::mqtt::MqttClient, settings::Settings};
+
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+fn availability_payload(available: bool) -> &'static str {
+    if available {
+        ONLINE
+    } else {
+        OFFLINE
+    }
+}
+
+/// Last will that marks hue-mqtt as offline if the connection to the MQTT
+/// broker is lost unexpectedly
+pub fn mk_status_last_will(settings: &Settings) -> LastWill {
+    LastWill::new(&settings.mqtt.status_topic, OFFLINE, QoS::AtLeastOnce, true)
+}
+
+/// Publishes whether hue-mqtt is running and able to reach the Hue bridge
+pub async fn publish_status(
+    mqtt_client: &MqttClient,
+    settings: &Settings,
+    online: bool,
+) -> Result<()> {
+    mqtt_client
+        .client
+        .publish(
+            &settings.mqtt.status_topic,
+            QoS::AtLeastOnce,
+            true,
+            availability_payload(online),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Publishes whether a Hue device is reachable over zigbee
+pub async fn publish_device_availability(
+    mqtt_client: &MqttClient,
+    settings: &Settings,
+    device_id: &str,
+    available: bool,
+) -> Result<()> {
+    let topic = settings
+        .mqtt
+        .device_availability_topic
+        .replace("{id}", device_id);
+
+    mqtt_client
+        .client
+        .publish(
+            topic,
+            QoS::AtLeastOnce,
+            true,
+            availability_payload(available),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Clears the retained availability of a device that has been removed from
+/// the Hue bridge
+pub async fn clear_device_availability(
+    mqtt_client: &MqttClient,
+    settings: &Settings,
+    device_id: &str,
+) -> Result<()> {
+    let topic = settings
+        .mqtt
+        .device_availability_topic
+        .replace("{id}", device_id);
+
+    mqtt_client
+        .client
+        .publish(topic, QoS::AtLeastOnce, true, vec![])
+        .await?;
+
+    Ok(())
+}
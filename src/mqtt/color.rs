@@ -0,0 +1,514 @@
+use color_eyre::Result;
+use palette::{convert::FromColorUnclamped, FromColor, IntoColor, LinSrgb, Srgb, Yxy};
+use serde::Serialize;
+
+use super::mqtt_device::{Ct, DeviceColor, Gamut, Hsv, Rgb, Xy};
+
+/// Range of color temperatures supported by Hue lights, in mireds
+const MIN_MIRED: u16 = 153;
+const MAX_MIRED: u16 = 500;
+
+/// Same range as `MIN_MIRED` - `MAX_MIRED`, in Kelvin
+const MIN_KELVIN: u16 = (1_000_000 / MAX_MIRED as u32) as u16;
+const MAX_KELVIN: u16 = (1_000_000 / MIN_MIRED as u32) as u16;
+
+fn validate_kelvin(kelvin: u16) -> Result<Ct> {
+    if !(MIN_KELVIN..=MAX_KELVIN).contains(&kelvin) {
+        return Err(eyre!(
+            "Invalid color temperature {kelvin}, expected {MIN_KELVIN} - {MAX_KELVIN}"
+        ));
+    }
+
+    Ok(Ct { ct: kelvin })
+}
+
+/// A color in one of the representations understood by the Hue bridge
+#[derive(Clone, Debug, PartialEq)]
+pub enum HueColor {
+    Xy(Xy),
+    Ct(Ct),
+}
+
+impl From<HueColor> for DeviceColor {
+    fn from(color: HueColor) -> Self {
+        match color {
+            HueColor::Xy(xy) => DeviceColor::Xy(xy),
+            HueColor::Ct(ct) => DeviceColor::Ct(ct),
+        }
+    }
+}
+
+/// Returns the CIE xy chromaticity of an sRGB color
+fn srgb_to_xy(srgb: Srgb) -> Xy {
+    let yxy: Yxy = srgb.into_linear().into_color();
+
+    // Black has no chromaticity, fall back to the white point
+    if yxy.luma <= 0.0 {
+        let white: Yxy = LinSrgb::new(1.0, 1.0, 1.0).into_color();
+        return Xy {
+            x: white.x,
+            y: white.y,
+        };
+    }
+
+    Xy { x: yxy.x, y: yxy.y }
+}
+
+/// Returns the brightest sRGB color with the given CIE xy chromaticity
+fn xy_to_srgb(xy: &Xy) -> Srgb {
+    // Clamping is done below, as it would otherwise alter the hue
+    let rgb = LinSrgb::from_color_unclamped(Yxy::new(xy.x, xy.y, 1.0));
+
+    // Colors outside the sRGB gamut have negative components
+    let rgb = LinSrgb::new(rgb.red.max(0.0), rgb.green.max(0.0), rgb.blue.max(0.0));
+    let max = rgb.red.max(rgb.green).max(rgb.blue);
+
+    if max <= 0.0 {
+        return Srgb::new(1.0, 1.0, 1.0);
+    }
+
+    Srgb::from_linear(rgb / max)
+}
+
+/// Returns the CIE xy chromaticity of a point on the Planckian locus, using
+/// the cubic spline approximation by Kim et al.
+fn ct_to_xy(ct: u16) -> Xy {
+    let t = (ct as f64).clamp(1667.0, 25000.0);
+
+    let x = if t <= 4000.0 {
+        -0.2661239e9 / t.powi(3) - 0.2343589e6 / t.powi(2) + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / t.powi(3) + 2.1070379e6 / t.powi(2) + 0.2226347e3 / t + 0.240390
+    };
+
+    let y = if t <= 2222.0 {
+        -1.1063814 * x.powi(3) - 1.34811020 * x.powi(2) + 2.18555832 * x - 0.20219683
+    } else if t <= 4000.0 {
+        -0.9549476 * x.powi(3) - 1.37418593 * x.powi(2) + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x.powi(3) - 5.87338670 * x.powi(2) + 3.75112997 * x - 0.37001483
+    };
+
+    Xy {
+        x: x as f32,
+        y: y as f32,
+    }
+}
+
+impl DeviceColor {
+    /// Converts the color into a representation understood by the Hue
+    /// bridge
+    pub fn to_hue_color(&self) -> Result<HueColor> {
+        let color = match self {
+            DeviceColor::Xy(xy) => HueColor::Xy(xy.clone()),
+            DeviceColor::Ct(ct) => HueColor::Ct(validate_kelvin(ct.ct)?),
+            DeviceColor::Kelvin(kelvin) => HueColor::Ct(validate_kelvin(kelvin.kelvin)?),
+            DeviceColor::Mired(mired) => {
+                if !(MIN_MIRED..=MAX_MIRED).contains(&mired.mired) {
+                    return Err(eyre!(
+                        "Invalid mired value {}, expected {MIN_MIRED} - {MAX_MIRED}",
+                        mired.mired
+                    ));
+                }
+
+                // Rounding 153 mired up would end up just outside the range
+                let kelvin = (1_000_000.0 / mired.mired as f32).round() as u16;
+
+                HueColor::Ct(Ct {
+                    ct: kelvin.clamp(MIN_KELVIN, MAX_KELVIN),
+                })
+            }
+            DeviceColor::Hsv(hsv) => {
+                let srgb: Srgb = palette::Hsv::new(hsv.hue, hsv.saturation, 1.0).into_color();
+                HueColor::Xy(srgb_to_xy(srgb))
+            }
+            DeviceColor::Rgb(rgb) => {
+                let srgb = Srgb::new(rgb.r, rgb.g, rgb.b).into_format();
+                HueColor::Xy(srgb_to_xy(srgb))
+            }
+            DeviceColor::Hex(hex) => {
+                let srgb: Srgb<u8> = hex
+                    .hex
+                    .parse()
+                    .map_err(|e| eyre!("Invalid hex color {}: {e}", hex.hex))?;
+                HueColor::Xy(srgb_to_xy(srgb.into_format()))
+            }
+        };
+
+        Ok(color)
+    }
+
+    /// Returns the CIE xy chromaticity of the color
+    fn to_xy(&self) -> Result<Xy> {
+        // Reported color temperatures are used as is, even if outside the
+        // range accepted in commands
+        if let DeviceColor::Ct(ct) = self {
+            return Ok(ct_to_xy(ct.ct));
+        }
+
+        match self.to_hue_color()? {
+            HueColor::Xy(xy) => Ok(xy),
+            HueColor::Ct(ct) => Ok(ct_to_xy(ct.ct)),
+        }
+    }
+}
+
+/// Returns the point closest to `p` on the line segment from `a` to `b`
+fn closest_point_on_segment(p: &Xy, a: &Xy, b: &Xy) -> Xy {
+    let (abx, aby) = (b.x - a.x, b.y - a.y);
+    let (apx, apy) = (p.x - a.x, p.y - a.y);
+
+    let length_squared = abx * abx + aby * aby;
+
+    // Degenerate gamuts may have two identical corners
+    if length_squared == 0.0 {
+        return a.clone();
+    }
+
+    let t = ((apx * abx + apy * aby) / length_squared).clamp(0.0, 1.0);
+
+    Xy {
+        x: a.x + abx * t,
+        y: a.y + aby * t,
+    }
+}
+
+fn distance(a: &Xy, b: &Xy) -> f32 {
+    ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+}
+
+/// Returns which side of the line from `a` to `b` the point `p` is on
+fn cross(p: &Xy, a: &Xy, b: &Xy) -> f32 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+impl Gamut {
+    pub fn contains(&self, xy: &Xy) -> bool {
+        let d1 = cross(xy, &self.red, &self.green);
+        let d2 = cross(xy, &self.green, &self.blue);
+        let d3 = cross(xy, &self.blue, &self.red);
+
+        let has_negative = d1 < 0.0 || d2 < 0.0 || d3 < 0.0;
+        let has_positive = d1 > 0.0 || d2 > 0.0 || d3 > 0.0;
+
+        !(has_negative && has_positive)
+    }
+
+    /// Returns the color closest to `xy` that is within the gamut
+    pub fn project(&self, xy: &Xy) -> Xy {
+        if self.contains(xy) {
+            return xy.clone();
+        }
+
+        [
+            closest_point_on_segment(xy, &self.red, &self.green),
+            closest_point_on_segment(xy, &self.green, &self.blue),
+            closest_point_on_segment(xy, &self.blue, &self.red),
+        ]
+        .into_iter()
+        .min_by(|a, b| distance(xy, a).total_cmp(&distance(xy, b)))
+        .unwrap()
+    }
+}
+
+/// The color of a light in additional color spaces, published alongside the
+/// light state if `publish_color_spaces` is enabled
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ColorSpaces {
+    pub hsv: Hsv,
+    pub rgb: Rgb,
+    pub hex: String,
+}
+
+impl ColorSpaces {
+    pub fn new(color: &DeviceColor) -> Option<Self> {
+        let srgb = xy_to_srgb(&color.to_xy().ok()?);
+        let hsv = palette::Hsv::from_color(srgb);
+        let rgb: Srgb<u8> = srgb.into_format();
+
+        Some(ColorSpaces {
+            hsv: Hsv {
+                hue: hsv.hue.into_positive_degrees(),
+                saturation: hsv.saturation,
+                value: hsv.value,
+            },
+            rgb: Rgb {
+                r: rgb.red,
+                g: rgb.green,
+                b: rgb.blue,
+            },
+            hex: format!("#{rgb:x}"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mqtt::mqtt_device::Mired;
+
+    fn hue_color(json: &str) -> Result<HueColor> {
+        serde_json::from_str::<DeviceColor>(json)
+            .unwrap()
+            .to_hue_color()
+    }
+
+    fn assert_xy(color: HueColor, x: f32, y: f32) {
+        let HueColor::Xy(xy) = color else {
+            panic!("Expected xy color, got {color:?}");
+        };
+
+        assert!(
+            (xy.x - x).abs() < 0.001 && (xy.y - y).abs() < 0.001,
+            "Expected ({x}, {y}), got ({}, {})",
+            xy.x,
+            xy.y
+        );
+    }
+
+    #[test]
+    fn xy_and_ct_are_unchanged() {
+        assert_xy(hue_color(r#"{"x": 0.4, "y": 0.3}"#).unwrap(), 0.4, 0.3);
+        assert_eq!(
+            hue_color(r#"{"ct": 2700}"#).unwrap(),
+            HueColor::Ct(Ct { ct: 2700 })
+        );
+    }
+
+    #[test]
+    fn kelvin_to_ct() {
+        assert_eq!(
+            hue_color(r#"{"kelvin": 4000}"#).unwrap(),
+            HueColor::Ct(Ct { ct: 4000 })
+        );
+        assert_eq!(
+            hue_color(r#"{"kelvin": 2000}"#).unwrap(),
+            HueColor::Ct(Ct { ct: 2000 })
+        );
+        assert_eq!(
+            hue_color(r#"{"kelvin": 6535}"#).unwrap(),
+            HueColor::Ct(Ct { ct: 6535 })
+        );
+    }
+
+    #[test]
+    fn kelvin_out_of_range() {
+        assert!(hue_color(r#"{"kelvin": 0}"#).is_err());
+        assert!(hue_color(r#"{"kelvin": 1999}"#).is_err());
+        assert!(hue_color(r#"{"kelvin": 6536}"#).is_err());
+        assert!(hue_color(r#"{"ct": 0}"#).is_err());
+        assert!(hue_color(r#"{"ct": 10000}"#).is_err());
+    }
+
+    #[test]
+    fn mired_to_ct() {
+        assert_eq!(
+            hue_color(r#"{"mired": 370}"#).unwrap(),
+            HueColor::Ct(Ct { ct: 2703 })
+        );
+        assert_eq!(
+            hue_color(r#"{"mired": 153}"#).unwrap(),
+            HueColor::Ct(Ct { ct: 6535 })
+        );
+        assert_eq!(
+            hue_color(r#"{"mired": 500}"#).unwrap(),
+            HueColor::Ct(Ct { ct: 2000 })
+        );
+    }
+
+    #[test]
+    fn mired_out_of_range() {
+        assert!(hue_color(r#"{"mired": 0}"#).is_err());
+        assert!(hue_color(r#"{"mired": 152}"#).is_err());
+        assert!(hue_color(r#"{"mired": 501}"#).is_err());
+    }
+
+    #[test]
+    fn hsv_to_xy() {
+        assert_xy(
+            hue_color(r#"{"hue": 0, "saturation": 1}"#).unwrap(),
+            0.64,
+            0.33,
+        );
+        assert_xy(
+            hue_color(r#"{"hue": 240, "saturation": 1}"#).unwrap(),
+            0.15,
+            0.06,
+        );
+
+        // Only hue and saturation matter
+        assert_xy(
+            hue_color(r#"{"hue": 120, "saturation": 1, "value": 0.1}"#).unwrap(),
+            0.30,
+            0.60,
+        );
+
+        // No saturation is the sRGB white point
+        assert_xy(
+            hue_color(r#"{"hue": 0, "saturation": 0}"#).unwrap(),
+            0.3127,
+            0.3290,
+        );
+    }
+
+    #[test]
+    fn rgb_to_xy() {
+        assert_xy(
+            hue_color(r#"{"r": 255, "g": 0, "b": 0}"#).unwrap(),
+            0.64,
+            0.33,
+        );
+        assert_xy(
+            hue_color(r#"{"r": 255, "g": 255, "b": 255}"#).unwrap(),
+            0.3127,
+            0.3290,
+        );
+
+        // Black falls back to the white point
+        assert_xy(
+            hue_color(r#"{"r": 0, "g": 0, "b": 0}"#).unwrap(),
+            0.3127,
+            0.3290,
+        );
+    }
+
+    #[test]
+    fn hex_to_xy() {
+        assert_xy(hue_color(r##"{"hex": "#00ff00"}"##).unwrap(), 0.30, 0.60);
+        assert_xy(hue_color(r##"{"hex": "#0000ff"}"##).unwrap(), 0.15, 0.06);
+        assert!(hue_color(r##"{"hex": "#ff00"}"##).is_err());
+        assert!(hue_color(r##"{"hex": "orange"}"##).is_err());
+    }
+
+    /// Gamut C of recent Hue lights
+    fn gamut_c() -> Gamut {
+        Gamut {
+            red: Xy {
+                x: 0.6915,
+                y: 0.3083,
+            },
+            green: Xy { x: 0.17, y: 0.7 },
+            blue: Xy {
+                x: 0.1532,
+                y: 0.0475,
+            },
+        }
+    }
+
+    fn assert_close(a: &Xy, b: &Xy) {
+        assert!(distance(a, b) < 0.0001, "Expected {b:?}, got {a:?}");
+    }
+
+    #[test]
+    fn gamut_contains_point_inside() {
+        let gamut = gamut_c();
+        let white = Xy {
+            x: 0.3127,
+            y: 0.329,
+        };
+
+        assert!(gamut.contains(&white));
+        assert_eq!(gamut.project(&white), white);
+    }
+
+    #[test]
+    fn gamut_contains_corners() {
+        let gamut = gamut_c();
+
+        assert!(gamut.contains(&gamut.red));
+        assert!(gamut.contains(&gamut.green));
+        assert!(gamut.contains(&gamut.blue));
+    }
+
+    #[test]
+    fn gamut_projects_point_past_edges() {
+        let gamut = gamut_c();
+
+        // Moves each edge's midpoint outwards, away from the opposite corner
+        for (a, b, opposite) in [
+            (&gamut.red, &gamut.green, &gamut.blue),
+            (&gamut.green, &gamut.blue, &gamut.red),
+            (&gamut.blue, &gamut.red, &gamut.green),
+        ] {
+            let midpoint = Xy {
+                x: (a.x + b.x) / 2.0,
+                y: (a.y + b.y) / 2.0,
+            };
+
+            // Perpendicular to the edge, pointing away from the opposite
+            // corner
+            let (dx, dy) = (b.x - a.x, b.y - a.y);
+            let length = (dx * dx + dy * dy).sqrt();
+            let mut normal = (dy / length, -dx / length);
+            if normal.0 * (opposite.x - midpoint.x) + normal.1 * (opposite.y - midpoint.y) > 0.0 {
+                normal = (-normal.0, -normal.1);
+            }
+
+            let outside = Xy {
+                x: midpoint.x + normal.0 * 0.02,
+                y: midpoint.y + normal.1 * 0.02,
+            };
+
+            assert!(!gamut.contains(&outside));
+            assert_close(&gamut.project(&outside), &midpoint);
+        }
+    }
+
+    #[test]
+    fn gamut_projects_point_near_corners() {
+        let gamut = gamut_c();
+
+        let outside_red = Xy { x: 0.75, y: 0.3 };
+        let outside_green = Xy { x: 0.16, y: 0.8 };
+        let outside_blue = Xy { x: 0.14, y: 0.02 };
+
+        for (outside, corner) in [
+            (outside_red, &gamut.red),
+            (outside_green, &gamut.green),
+            (outside_blue, &gamut.blue),
+        ] {
+            assert!(!gamut.contains(&outside));
+            assert_close(&gamut.project(&outside), corner);
+        }
+    }
+
+    #[test]
+    fn closest_point_on_zero_length_segment() {
+        let a = Xy { x: 0.3, y: 0.3 };
+
+        assert_eq!(closest_point_on_segment(&Xy { x: 0.5, y: 0.4 }, &a, &a), a);
+    }
+
+    #[test]
+    fn degenerate_gamut() {
+        let gamut = Gamut {
+            red: Xy { x: 0.3, y: 0.3 },
+            green: Xy { x: 0.3, y: 0.3 },
+            blue: Xy { x: 0.5, y: 0.3 },
+        };
+
+        assert_close(
+            &gamut.project(&Xy { x: 0.4, y: 0.35 }),
+            &Xy { x: 0.4, y: 0.3 },
+        );
+        assert_close(&gamut.project(&Xy { x: 0.25, y: 0.35 }), &gamut.red);
+    }
+
+    #[test]
+    fn color_spaces_of_xy() {
+        let color_spaces = ColorSpaces::new(&DeviceColor::Xy(Xy { x: 0.64, y: 0.33 })).unwrap();
+
+        assert_eq!(color_spaces.rgb, Rgb { r: 255, g: 0, b: 0 });
+        assert_eq!(color_spaces.hex, "#ff0000");
+        assert!(color_spaces.hsv.hue.abs() < 1.0 || color_spaces.hsv.hue > 359.0);
+        assert!(color_spaces.hsv.saturation > 0.99);
+    }
+
+    #[test]
+    fn color_spaces_of_invalid_color() {
+        assert_eq!(
+            ColorSpaces::new(&DeviceColor::Mired(Mired { mired: 0 })),
+            None
+        );
+    }
+}
@@ -0,0 +1,302 @@
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+use tokio::{
+    sync::{Notify, RwLock},
+    time::Instant,
+};
+
+use super::events::MqttCommand;
+use crate::{metrics::metrics, settings::Settings};
+
+/// Commands received over MQTT that are waiting to be sent to the Hue bridge.
+///
+/// A command for a resource that already has a command waiting in the queue
+/// is merged into the waiting command, so that the queue never holds more than
+/// one command per resource.
+#[derive(Clone)]
+pub struct CommandQueue {
+    commands: Arc<RwLock<VecDeque<MqttCommand>>>,
+    max_len: usize,
+
+    /// Notify channel is used to send a notification to the Hue bridge
+    /// communication task that the queue has changed
+    pub notify: Arc<Notify>,
+}
+
+impl CommandQueue {
+    pub fn new(settings: &Settings) -> CommandQueue {
+        CommandQueue {
+            commands: Default::default(),
+            max_len: settings.hue_bridge.max_queued_commands.max(1),
+            notify: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Adds a command to the back of the queue, merging it into a waiting
+    /// command for the same resource if there is one.
+    pub async fn push(&self, command: MqttCommand) {
+        {
+            let mut commands = self.commands.write().await;
+
+            let waiting = commands.iter_mut().find(|c| c.id() == command.id());
+
+            match (waiting, command) {
+                (Some(MqttCommand::Device(waiting)), MqttCommand::Device(mqtt_device)) => {
+                    debug!("Merging queued command for {}", waiting.name);
+                    waiting.merge(mqtt_device);
+                    metrics().commands_coalesced.inc();
+                }
+                (Some(waiting), command) => {
+                    *waiting = command;
+                    metrics().commands_coalesced.inc();
+                }
+                (None, command) => self.push_back(&mut commands, command),
+            }
+
+            metrics().command_queue_depth.set(commands.len() as i64);
+        }
+
+        self.notify.notify_one();
+    }
+
+    /// Adds a retried command to the back of the queue, unless a newer
+    /// command for the same resource is already waiting to be sent.
+    pub async fn push_retry(&self, command: MqttCommand) {
+        {
+            let mut commands = self.commands.write().await;
+
+            if commands.iter().any(|c| c.id() == command.id()) {
+                return;
+            }
+
+            self.push_back(&mut commands, command);
+            metrics().command_queue_depth.set(commands.len() as i64);
+        }
+
+        self.notify.notify_one();
+    }
+
+    /// Adds a command to the back of the queue, dropping the oldest command
+    /// if the queue is full
+    fn push_back(&self, commands: &mut VecDeque<MqttCommand>, command: MqttCommand) {
+        if commands.len() >= self.max_len {
+            if let Some(dropped) = commands.pop_front() {
+                eprintln!(
+                    "Command queue is full, dropping command for {}",
+                    dropped.id()
+                );
+                metrics().commands_dropped.inc();
+            }
+        }
+
+        commands.push_back(command);
+    }
+
+    /// Removes and returns the first command accepted by `f`
+    pub async fn pop_first(&self, f: impl Fn(&MqttCommand) -> bool) -> Option<MqttCommand> {
+        let mut commands = self.commands.write().await;
+        let index = commands.iter().position(f)?;
+        let command = commands.remove(index);
+
+        metrics().command_queue_depth.set(commands.len() as i64);
+
+        command
+    }
+
+    /// Number of commands waiting to be sent
+    pub async fn depth(&self) -> usize {
+        self.commands.read().await.len()
+    }
+}
+
+/// Limits the rate at which commands are sent to the Hue bridge, while
+/// allowing short bursts of up to one second worth of commands.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: f64) -> TokenBucket {
+        let rate = rate.max(0.01);
+        let capacity = rate.max(1.0);
+
+        TokenBucket {
+            rate,
+            capacity,
+            tokens: capacity,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.refilled_at = now;
+    }
+
+    /// Returns how long to wait until a token is available, or `None` if a
+    /// token can be taken right away.
+    pub fn wait_time(&mut self) -> Option<Duration> {
+        self.refill();
+
+        if self.tokens >= 1.0 {
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+
+    /// Takes a token, should only be called after `wait_time` returns `None`
+    pub fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(max_len: usize) -> CommandQueue {
+        CommandQueue {
+            commands: Default::default(),
+            max_len,
+            notify: Arc::new(Notify::new()),
+        }
+    }
+
+    fn command(id: &str, brightness: f32) -> MqttCommand {
+        MqttCommand::Device(
+            serde_json::from_str(&format!(
+                r#"{{"id": "{id}", "name": "Lamp {id}", "brightness": {brightness}}}"#
+            ))
+            .unwrap(),
+        )
+    }
+
+    fn brightness(command: &MqttCommand) -> Option<f32> {
+        match command {
+            MqttCommand::Device(mqtt_device) => mqtt_device.brightness,
+            MqttCommand::SceneRecall { .. } => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn drops_oldest_command_when_full() {
+        let queue = queue(2);
+
+        queue.push(command("1", 0.1)).await;
+        queue.push(command("2", 0.2)).await;
+        queue.push(command("3", 0.3)).await;
+
+        assert_eq!(queue.depth().await, 2);
+        assert_eq!(queue.pop_first(|_| true).await.unwrap().id(), "2");
+        assert_eq!(queue.pop_first(|_| true).await.unwrap().id(), "3");
+    }
+
+    #[tokio::test]
+    async fn merging_does_not_drop_commands() {
+        let queue = queue(2);
+
+        queue.push(command("1", 0.1)).await;
+        queue.push(command("2", 0.2)).await;
+        queue.push(command("1", 0.5)).await;
+
+        assert_eq!(queue.depth().await, 2);
+
+        let first = queue.pop_first(|_| true).await.unwrap();
+        assert_eq!(first.id(), "1");
+        assert_eq!(brightness(&first), Some(0.5));
+    }
+
+    #[tokio::test]
+    async fn retry_skipped_when_newer_command_is_queued() {
+        let queue = queue(10);
+
+        queue.push(command("1", 0.5)).await;
+        queue.push_retry(command("1", 0.1)).await;
+
+        assert_eq!(queue.depth().await, 1);
+        assert_eq!(
+            brightness(&queue.pop_first(|_| true).await.unwrap()),
+            Some(0.5)
+        );
+    }
+
+    #[tokio::test]
+    async fn retry_queued_when_no_command_is_waiting() {
+        let queue = queue(10);
+
+        queue.push(command("2", 0.5)).await;
+        queue.push_retry(command("1", 0.1)).await;
+
+        assert_eq!(queue.depth().await, 2);
+    }
+
+    #[tokio::test]
+    async fn retry_drops_oldest_command_when_full() {
+        let queue = queue(2);
+
+        queue.push(command("1", 0.1)).await;
+        queue.push(command("2", 0.2)).await;
+        queue.push_retry(command("3", 0.3)).await;
+
+        assert_eq!(queue.depth().await, 2);
+        assert_eq!(queue.pop_first(|_| true).await.unwrap().id(), "2");
+        assert_eq!(queue.pop_first(|_| true).await.unwrap().id(), "3");
+    }
+
+    #[tokio::test]
+    async fn pop_first_skips_commands_in_flight() {
+        let queue = queue(10);
+        let in_flight = ["1".to_string()];
+
+        queue.push(command("1", 0.1)).await;
+        queue.push(command("2", 0.2)).await;
+
+        let command = queue
+            .pop_first(|c| !in_flight.iter().any(|id| id == c.id()))
+            .await
+            .unwrap();
+        assert_eq!(command.id(), "2");
+
+        assert!(queue
+            .pop_first(|c| !in_flight.iter().any(|id| id == c.id()))
+            .await
+            .is_none());
+        assert_eq!(queue.depth().await, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn token_bucket_refills_over_time() {
+        let mut bucket = TokenBucket::new(2.0);
+
+        // Starts with a full burst of tokens
+        for _ in 0..2 {
+            assert_eq!(bucket.wait_time(), None);
+            bucket.take();
+        }
+
+        assert_eq!(bucket.wait_time(), Some(Duration::from_millis(500)));
+
+        tokio::time::advance(Duration::from_millis(250)).await;
+        assert_eq!(bucket.wait_time(), Some(Duration::from_millis(250)));
+
+        tokio::time::advance(Duration::from_millis(250)).await;
+        assert_eq!(bucket.wait_time(), None);
+        bucket.take();
+
+        // Does not fill up past capacity
+        tokio::time::advance(Duration::from_secs(10)).await;
+        for _ in 0..2 {
+            assert_eq!(bucket.wait_time(), None);
+            bucket.take();
+        }
+        assert!(bucket.wait_time().is_some());
+    }
+}
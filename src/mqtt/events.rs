@@ -1,13 +1,68 @@
+use std::{collections::HashSet, future::Future, sync::Arc};
+
 use color_eyre::Result;
 use rumqttc::QoS;
+use tokio::sync::{RwLock, Semaphore};
 
 use crate::{
-    hue::rest::light::{put_hue_light, PutResponse},
-    mqtt::mqtt_device::MqttDevice,
+    hue::{
+        events::EventsourceState,
+        rest::{
+            grouped_light::put_hue_grouped_light,
+            light::{put_hue_light, PutResponse},
+            scene::put_hue_scene_recall,
+        },
+    },
+    metrics::metrics,
+    mqtt::{
+        availability::publish_status,
+        command_queue::TokenBucket,
+        mqtt_device::{Ct, DeviceColor, MqttDevice},
+        mqtt_scene::MqttSceneRecall,
+    },
     protocols::{https::HyperHttpsClient, mqtt::MqttClient},
     settings::Settings,
 };
 
+/// A command received over MQTT that is waiting to be sent to the Hue bridge
+#[derive(Clone, Debug)]
+pub enum MqttCommand {
+    Device(MqttDevice),
+    SceneRecall { id: String, recall: MqttSceneRecall },
+}
+
+impl MqttCommand {
+    /// ID of the Hue resource targeted by this command
+    pub fn id(&self) -> &str {
+        match self {
+            MqttCommand::Device(device) => &device.id,
+            MqttCommand::SceneRecall { id, .. } => id,
+        }
+    }
+
+    /// Whether this command is subject to the Hue bridge's rate limit for
+    /// group commands
+    pub fn is_group_command(&self) -> bool {
+        match self {
+            MqttCommand::Device(device) => device.is_group,
+            MqttCommand::SceneRecall { .. } => true,
+        }
+    }
+}
+
+/// Extracts the `{id}` part of `topic` if it matches the given topic template
+fn topic_id<'a>(topic_template: &str, topic: &'a str) -> Option<&'a str> {
+    if !rumqttc::matches(topic, &topic_template.replace("{id}", "+")) {
+        return None;
+    }
+
+    topic_template
+        .split('/')
+        .zip(topic.split('/'))
+        .find(|(level, _)| *level == "{id}")
+        .map(|(_, id)| id)
+}
+
 pub async fn handle_incoming_mqtt_event(
     event: rumqttc::Event,
     mqtt_client: &MqttClient,
@@ -15,6 +70,9 @@ pub async fn handle_incoming_mqtt_event(
 ) -> Result<()> {
     match event {
         rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_)) => {
+            *mqtt_client.connected.write().await = true;
+            publish_status(mqtt_client, settings, true).await?;
+
             mqtt_client
                 .client
                 .subscribe(
@@ -22,19 +80,42 @@ pub async fn handle_incoming_mqtt_event(
                     QoS::AtMostOnce,
                 )
                 .await?;
+            mqtt_client
+                .client
+                .subscribe(
+                    settings.mqtt.group_topic_set.replace("{id}", "+"),
+                    QoS::AtMostOnce,
+                )
+                .await?;
+            mqtt_client
+                .client
+                .subscribe(
+                    settings.mqtt.scene_topic_recall.replace("{id}", "+"),
+                    QoS::AtMostOnce,
+                )
+                .await?;
         }
         rumqttc::Event::Incoming(rumqttc::Packet::Publish(msg)) => {
-            let device: MqttDevice = serde_json::from_slice(&msg.payload)?;
+            let command = if let Some(id) = topic_id(&settings.mqtt.scene_topic_recall, &msg.topic)
+            {
+                let recall: MqttSceneRecall = serde_json::from_slice(&msg.payload)?;
 
-            // Push device update to the unhandled messages
-            // queue, removing any existing unhandled messages
-            // for the same device.
-            let mut unhandled_messages = mqtt_client.unhandled_messages.write().await;
-            unhandled_messages.retain(|d: &MqttDevice| d.id != device.id);
-            unhandled_messages.push_back(device);
+                MqttCommand::SceneRecall {
+                    id: id.to_string(),
+                    recall,
+                }
+            } else {
+                let mut device: MqttDevice = serde_json::from_slice(&msg.payload)?;
+                device.color = device
+                    .color
+                    .map(|color| color.to_hue_color().map(DeviceColor::from))
+                    .transpose()?;
+                device.is_group = topic_id(&settings.mqtt.group_topic_set, &msg.topic).is_some();
 
-            // Notify Hue bridge communication task that there are new messages
-            mqtt_client.notify.notify_one();
+                MqttCommand::Device(device)
+            };
+
+            mqtt_client.command_queue.push(command).await;
         }
         _ => {}
     }
@@ -42,57 +123,245 @@ pub async fn handle_incoming_mqtt_event(
     Ok(())
 }
 
+/// Sends commands from the command queue to the Hue bridge, limiting the
+/// rate of light and group commands separately and sending commands for up to
+/// `max_concurrent_commands` different resources at a time.
 pub fn start_mqtt_events_loop(
     mqtt_client: &MqttClient,
     settings: &Settings,
     https_client: &HyperHttpsClient,
+    state: &EventsourceState,
 ) {
-    let unhandled_messages = mqtt_client.unhandled_messages.clone();
-    let notify = mqtt_client.notify.clone();
+    let command_queue = mqtt_client.command_queue.clone();
 
     let settings = settings.clone();
     let https_client = https_client.clone();
+    let state = state.clone();
 
     tokio::spawn(async move {
+        let mut light_bucket = TokenBucket::new(settings.hue_bridge.commands_per_second);
+        let mut group_bucket = TokenBucket::new(settings.hue_bridge.group_commands_per_second);
+
+        // Commands for the same resource are never sent concurrently, so that
+        // they reach the Hue bridge in the order they were received
+        let in_flight: Arc<RwLock<HashSet<String>>> = Default::default();
+        let semaphore = Arc::new(Semaphore::new(
+            settings.hue_bridge.max_concurrent_commands.max(1),
+        ));
+
         loop {
-            let next_message = {
-                let mut unhandled_messages = unhandled_messages.write().await;
-                unhandled_messages.pop_front()
+            let Ok(permit) = semaphore.clone().acquire_owned().await else {
+                break;
             };
 
-            match next_message {
-                Some(message) => {
-                    let result =
-                        process_next_mqtt_message(message, settings.clone(), https_client.clone())
-                            .await;
+            let light_wait = light_bucket.wait_time();
+            let group_wait = group_bucket.wait_time();
+            let busy_ids = in_flight.read().await.clone();
+
+            let next_command = command_queue
+                .pop_first(|command| {
+                    let wait = if command.is_group_command() {
+                        group_wait
+                    } else {
+                        light_wait
+                    };
+
+                    wait.is_none() && !busy_ids.contains(command.id())
+                })
+                .await;
 
-                    if let Err(e) = result {
-                        eprintln!("Error while processing MQTT message: {:?}", e);
+            let Some(command) = next_command else {
+                // Wait until the queue changes or a rate limit expires
+                match light_wait.into_iter().chain(group_wait).min() {
+                    Some(wait) => {
+                        tokio::select! {
+                            _ = command_queue.notify.notified() => {}
+                            _ = tokio::time::sleep(wait) => {}
+                        }
                     }
+                    None => command_queue.notify.notified().await,
                 }
-                None => {
-                    // Wait until we get notified that there are new messages.
-                    notify.notified().await;
-                }
+
+                continue;
+            };
+
+            if command.is_group_command() {
+                group_bucket.take();
+            } else {
+                light_bucket.take();
             }
+
+            debug!(
+                "Sending command for {}, {} commands queued",
+                command.id(),
+                command_queue.depth().await
+            );
+
+            in_flight.write().await.insert(command.id().to_string());
+
+            let command_queue = command_queue.clone();
+            let in_flight = in_flight.clone();
+            let settings = settings.clone();
+            let https_client = https_client.clone();
+            let state = state.clone();
+
+            tokio::spawn(async move {
+                let id = command.id().to_string();
+                let result =
+                    process_next_mqtt_message(command, settings, https_client, &state).await;
+
+                if let Err(e) = result {
+                    eprintln!("Error while processing MQTT message: {:?}", e);
+                }
+
+                in_flight.write().await.remove(&id);
+                drop(permit);
+
+                // Commands for this resource may now be sent
+                command_queue.notify.notify_one();
+            });
         }
     });
 }
 
+/// Replaces a toggle with the opposite of the last known power state of the
+/// light or group
+async fn resolve_toggle(state: &EventsourceState, mqtt_device: &mut MqttDevice) {
+    let mqtt_devices = state.mqtt_devices.read().await;
+    let power = mqtt_devices
+        .get(&mqtt_device.id)
+        .and_then(|light| light.power)
+        .unwrap_or(false);
+
+    mqtt_device.power = Some(!power);
+    mqtt_device.toggle = false;
+}
+
+/// Replaces a requested xy color with the closest color the light is able to
+/// reproduce, so that the state reported back by the light matches the
+/// requested state.
+async fn project_to_gamut(state: &EventsourceState, mqtt_device: &mut MqttDevice) {
+    let Some(DeviceColor::Xy(xy)) = &mqtt_device.color else {
+        return;
+    };
+
+    let mqtt_devices = state.mqtt_devices.read().await;
+    let gamut = mqtt_devices
+        .get(&mqtt_device.id)
+        .and_then(|light| light.capabilities.as_ref())
+        .and_then(|capabilities| capabilities.gamut.as_ref());
+
+    if let Some(gamut) = gamut {
+        mqtt_device.color = Some(DeviceColor::Xy(gamut.project(xy)));
+    }
+}
+
+/// Limits the requested color temperature to the range supported by the light.
+/// The bridge would do the same, but then the light never reports the
+/// requested state and the command would be retried.
+async fn clamp_to_ct_range(state: &EventsourceState, mqtt_device: &mut MqttDevice) {
+    let Some(DeviceColor::Ct(Ct { ct })) = &mqtt_device.color else {
+        return;
+    };
+
+    let mqtt_devices = state.mqtt_devices.read().await;
+    let ct_range = mqtt_devices
+        .get(&mqtt_device.id)
+        .and_then(|light| light.capabilities.as_ref())
+        .and_then(|capabilities| capabilities.ct.as_ref());
+
+    if let Some(ct_range) = ct_range {
+        mqtt_device.color = Some(DeviceColor::Ct(Ct {
+            ct: (*ct).clamp(ct_range.start, ct_range.end),
+        }));
+    }
+}
+
+/// Records the duration and outcome of a PUT request to the Hue bridge
+async fn observe_put(
+    rtype: &str,
+    request: impl Future<Output = Result<PutResponse>>,
+) -> Result<PutResponse> {
+    let timer = metrics()
+        .put_duration
+        .with_label_values(&[rtype])
+        .start_timer();
+    let result = request.await;
+    timer.observe_duration();
+
+    if matches!(&result, Ok(response) if response.errors.is_empty()) {
+        metrics().commands_sent.inc();
+    } else {
+        metrics().put_errors.with_label_values(&[rtype]).inc();
+    }
+
+    result
+}
+
 async fn process_next_mqtt_message(
-    mqtt_device: MqttDevice,
+    command: MqttCommand,
     settings: Settings,
     https_client: HyperHttpsClient,
+    state: &EventsourceState,
 ) -> Result<Option<PutResponse>> {
-    let result = put_hue_light(&settings, &https_client, &mqtt_device).await?;
+    let mut mqtt_device = match command {
+        MqttCommand::Device(mqtt_device) => mqtt_device,
+        MqttCommand::SceneRecall { id, recall } => {
+            let result = observe_put(
+                "scene",
+                put_hue_scene_recall(&settings, &https_client, &id, recall.into()),
+            )
+            .await?;
+
+            if !result.errors.is_empty() {
+                return Err(eyre!(
+                    "Error while sending PUT to Hue scene resource (id: {}):\n{:#?}",
+                    id,
+                    result.errors
+                ));
+            }
+
+            return Ok(Some(result));
+        }
+    };
+
+    if mqtt_device.toggle {
+        resolve_toggle(state, &mut mqtt_device).await;
+    }
+
+    if !mqtt_device.is_group {
+        project_to_gamut(state, &mut mqtt_device).await;
+        clamp_to_ct_range(state, &mut mqtt_device).await;
+    }
+
+    let result = if mqtt_device.is_group {
+        observe_put(
+            "grouped_light",
+            put_hue_grouped_light(&settings, &https_client, &mqtt_device),
+        )
+        .await?
+    } else {
+        observe_put(
+            "light",
+            put_hue_light(&settings, &https_client, &mqtt_device),
+        )
+        .await?
+    };
 
     if !result.errors.is_empty() {
         Err(eyre!(
-            "Error while sending PUT to Hue light resource (name: {}):\n{:#?}",
+            "Error while sending PUT to Hue {} resource (name: {}):\n{:#?}",
+            if mqtt_device.is_group {
+                "grouped_light"
+            } else {
+                "light"
+            },
             mqtt_device.name,
             result.errors
         ))
     } else {
+        state.pending_commands.track(&mqtt_device).await;
+
         Ok(Some(result))
     }
 }
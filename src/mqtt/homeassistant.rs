@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+
+use color_eyre::Result;
+use serde::Serialize;
+
+use crate::{
+    hue::rest::{device::DeviceData, HueState},
+    protocols::mqtt::MqttClient,
+    settings::{HomeAssistantSettings, Settings},
+};
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+struct DiscoveryDevice {
+    identifiers: Vec<String>,
+    name: String,
+    manufacturer: String,
+    model: String,
+}
+
+impl From<&DeviceData> for DiscoveryDevice {
+    fn from(device: &DeviceData) -> Self {
+        DiscoveryDevice {
+            identifiers: vec![format!("hue-mqtt_{}", device.id)],
+            name: device.metadata.name.clone(),
+            manufacturer: device.product_data.manufacturer_name.clone(),
+            model: device.product_data.product_name.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+struct DiscoveryAvailability {
+    topic: String,
+}
+
+/// Home Assistant MQTT discovery payload. Only the fields relevant to the
+/// given component are set, see:
+/// https://www.home-assistant.io/integrations/mqtt/#mqtt-discovery
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+struct DiscoveryConfig {
+    /// A name of `None` makes Home Assistant use the device name as the
+    /// entity name.
+    name: Option<String>,
+    unique_id: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device: Option<DiscoveryDevice>,
+    state_topic: String,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    availability: Vec<DiscoveryAvailability>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    availability_mode: Option<&'static str>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value_template: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_class: Option<&'static str>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit_of_measurement: Option<&'static str>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state_class: Option<&'static str>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    command_topic: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload_on: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload_off: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state_value_template: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    brightness_command_topic: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    brightness_command_template: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    brightness_state_topic: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    brightness_value_template: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color_temp_command_topic: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color_temp_command_template: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color_temp_state_topic: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color_temp_value_template: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_mireds: Option<u16>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_mireds: Option<u16>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    xy_command_topic: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    xy_command_template: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    xy_state_topic: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    xy_value_template: Option<String>,
+}
+
+/// Builds a light set command containing the given JSON fields. The fields
+/// are spliced in verbatim, which allows them to contain Home Assistant
+/// template expressions.
+fn mk_light_command(id: &str, name: &str, fields: &str) -> String {
+    // Serializing a &str to JSON can't fail
+    let id = serde_json::to_string(id).unwrap();
+    let name = serde_json::to_string(name).unwrap();
+
+    format!(r#"{{"id":{id},"name":{name},{fields}}}"#)
+}
+
+/// Entities are available when hue-mqtt is online, and the owning device is
+/// reachable over zigbee.
+fn mk_availability(
+    settings: &Settings,
+    hue_state: &HueState,
+    device: &DeviceData,
+) -> Vec<DiscoveryAvailability> {
+    let mut availability = vec![DiscoveryAvailability {
+        topic: settings.mqtt.status_topic.clone(),
+    }];
+
+    // Only devices with a zigbee_connectivity resource will have their
+    // availability published
+    let has_zigbee_connectivity = hue_state
+        .zigbee_connectivity
+        .values()
+        .any(|zigbee_connectivity| zigbee_connectivity.owner.rid == device.id);
+
+    if has_zigbee_connectivity {
+        availability.push(DiscoveryAvailability {
+            topic: settings
+                .mqtt
+                .device_availability_topic
+                .replace("{id}", &device.id),
+        });
+    }
+
+    availability
+}
+
+fn mk_light_config(settings: &Settings, hue_state: &HueState, id: &str) -> Option<DiscoveryConfig> {
+    let light = hue_state.lights.get(id)?;
+    let device = hue_state.devices.get(&light.owner.rid)?;
+    let name = &device.metadata.name;
+
+    let state_topic = settings.mqtt.light_topic.replace("{id}", id);
+    let command_topic = settings.mqtt.light_topic_set.replace("{id}", id);
+
+    let payload_on = mk_light_command(id, name, r#""power":true"#);
+    let payload_off = mk_light_command(id, name, r#""power":false"#);
+
+    let mut config = DiscoveryConfig {
+        name: None,
+        unique_id: format!("hue-mqtt_{id}"),
+        device: Some(device.into()),
+        availability: mk_availability(settings, hue_state, device),
+        availability_mode: Some("all"),
+        state_topic: state_topic.clone(),
+        command_topic: Some(command_topic.clone()),
+        state_value_template: Some(format!(
+            "{{% if value_json.power %}}{payload_on}{{% else %}}{payload_off}{{% endif %}}"
+        )),
+        payload_on: Some(payload_on),
+        payload_off: Some(payload_off),
+        ..Default::default()
+    };
+
+    if light.dimming.is_some() {
+        config.brightness_command_topic = Some(command_topic.clone());
+        config.brightness_command_template = Some(mk_light_command(
+            id,
+            name,
+            r#""brightness":{{ value / 255 }}"#,
+        ));
+        config.brightness_state_topic = Some(state_topic.clone());
+        config.brightness_value_template = Some(
+            "{{ (value_json.brightness * 255) | round(0) | int if value_json.brightness is not none else 0 }}"
+                .to_string(),
+        );
+    }
+
+    if let Some(color_temperature) = &light.color_temperature {
+        let mirek_schema = color_temperature.mirek_schema.as_ref();
+
+        config.color_temp_command_topic = Some(command_topic.clone());
+        config.color_temp_command_template = Some(mk_light_command(
+            id,
+            name,
+            r#""color":{"ct":{{ (1000000 / value) | round(0) | int }}}"#,
+        ));
+        config.color_temp_state_topic = Some(state_topic.clone());
+        config.color_temp_value_template = Some(
+            "{{ (1000000 / value_json.color.ct) | round(0) | int if value_json.color is not none and value_json.color.ct is defined else None }}"
+                .to_string(),
+        );
+        config.min_mireds = Some(mirek_schema.map(|s| s.mirek_minimum).unwrap_or(153.0) as u16);
+        config.max_mireds = Some(mirek_schema.map(|s| s.mirek_maximum).unwrap_or(500.0) as u16);
+    }
+
+    if light.color.is_some() {
+        config.xy_command_topic = Some(command_topic);
+        config.xy_command_template = Some(mk_light_command(
+            id,
+            name,
+            r#""color":{"x":{{ x }},"y":{{ y }}}"#,
+        ));
+        config.xy_state_topic = Some(state_topic);
+        config.xy_value_template = Some(
+            "{{ value_json.color.x ~ ',' ~ value_json.color.y if value_json.color is not none and value_json.color.x is defined else None }}"
+                .to_string(),
+        );
+    }
+
+    Some(config)
+}
+
+fn mk_sensor_config(
+    settings: &Settings,
+    hue_state: &HueState,
+    device: &DeviceData,
+    id: &str,
+    name: String,
+) -> DiscoveryConfig {
+    DiscoveryConfig {
+        name: Some(name),
+        unique_id: format!("hue-mqtt_{id}"),
+        device: Some(device.into()),
+        availability: mk_availability(settings, hue_state, device),
+        availability_mode: Some("all"),
+        state_topic: settings.mqtt.sensor_topic.replace("{id}", id),
+        ..Default::default()
+    }
+}
+
+fn mk_binary_sensor_value_template(settings: &Settings) -> String {
+    if settings.mqtt.legacy_sensor_value {
+        "{{ 'ON' if value_json.sensor_value == 'true' else 'OFF' }}".to_string()
+    } else {
+        "{{ 'ON' if value_json.sensor_value.value else 'OFF' }}".to_string()
+    }
+}
+
+/// Computes Home Assistant discovery configs for all supported resources in
+/// the given Hue state, keyed by discovery topic.
+fn mk_discovery_configs(
+    settings: &Settings,
+    homeassistant: &HomeAssistantSettings,
+    hue_state: &HueState,
+) -> HashMap<String, DiscoveryConfig> {
+    let mut configs: Vec<(&str, &str, DiscoveryConfig)> = vec![];
+
+    for id in hue_state.lights.keys() {
+        if let Some(config) = mk_light_config(settings, hue_state, id) {
+            configs.push(("light", id, config));
+        }
+    }
+
+    for button in hue_state.buttons.values() {
+        if let Some(device) = hue_state.devices.get(&button.owner.rid) {
+            let name = format!("Button {}", button.metadata.control_id);
+            let mut config = mk_sensor_config(settings, hue_state, device, &button.id, name);
+            config.value_template = Some(mk_binary_sensor_value_template(settings));
+
+            configs.push(("binary_sensor", &button.id, config));
+        }
+    }
+
+    for motion in hue_state.motion.values() {
+        if let Some(device) = hue_state.devices.get(&motion.owner.rid) {
+            let mut config = mk_sensor_config(
+                settings,
+                hue_state,
+                device,
+                &motion.id,
+                "Motion".to_string(),
+            );
+            config.device_class = Some("motion");
+            config.value_template = Some(mk_binary_sensor_value_template(settings));
+
+            configs.push(("binary_sensor", &motion.id, config));
+        }
+    }
+
+    for temperature in hue_state.temperature.values() {
+        if let Some(device) = hue_state.devices.get(&temperature.owner.rid) {
+            let name = "Temperature".to_string();
+            let mut config = mk_sensor_config(settings, hue_state, device, &temperature.id, name);
+            config.device_class = Some("temperature");
+            config.unit_of_measurement = Some("°C");
+            config.state_class = Some("measurement");
+            config.value_template = Some(if settings.mqtt.legacy_sensor_value {
+                "{{ value_json.sensor_value | float }}".to_string()
+            } else {
+                "{{ value_json.sensor_value.value }}".to_string()
+            });
+
+            configs.push(("sensor", &temperature.id, config));
+        }
+    }
+
+    for light_level in hue_state.light_level.values() {
+        if let Some(device) = hue_state.devices.get(&light_level.owner.rid) {
+            let name = "Light level".to_string();
+            let mut config = mk_sensor_config(settings, hue_state, device, &light_level.id, name);
+            config.device_class = Some("illuminance");
+            config.unit_of_measurement = Some("lx");
+            config.state_class = Some("measurement");
+
+            // Legacy sensor values contain the light level as reported by
+            // Hue, 10000 * log10(lux) + 1
+            config.value_template = Some(if settings.mqtt.legacy_sensor_value {
+                "{{ (10 ** ((value_json.sensor_value | float - 1) / 10000)) | round(1) }}"
+                    .to_string()
+            } else {
+                "{{ value_json.sensor_value.value }}".to_string()
+            });
+
+            configs.push(("sensor", &light_level.id, config));
+        }
+    }
+
+    for device_power in hue_state.device_power.values() {
+        if let Some(device) = hue_state.devices.get(&device_power.owner.rid) {
+            let name = "Battery".to_string();
+            let mut config = mk_sensor_config(settings, hue_state, device, &device_power.id, name);
+            config.state_topic = settings
+                .mqtt
+                .device_battery_topic
+                .replace("{id}", &device.id);
+            config.device_class = Some("battery");
+            config.unit_of_measurement = Some("%");
+            config.state_class = Some("measurement");
+            config.value_template = Some("{{ value_json.battery_level }}".to_string());
+
+            configs.push(("sensor", &device_power.id, config));
+        }
+    }
+
+    configs
+        .into_iter()
+        .map(|(component, id, config)| {
+            let topic = format!(
+                "{}/{}/{}/config",
+                homeassistant.discovery_prefix, component, id
+            );
+
+            (topic, config)
+        })
+        .collect()
+}
+
+/// Publishes Home Assistant discovery configs for the given Hue state.
+///
+/// `published` holds the configs that were previously published by us. Only
+/// changed configs are re-published, and configs of resources that have
+/// disappeared from the bridge are cleared by publishing an empty retained
+/// message to their discovery topic.
+pub async fn publish_homeassistant_discovery(
+    settings: &Settings,
+    mqtt_client: &MqttClient,
+    hue_state: &HueState,
+    published: &mut HashMap<String, String>,
+) -> Result<()> {
+    let Some(homeassistant) = &settings.homeassistant else {
+        return Ok(());
+    };
+
+    let configs = mk_discovery_configs(settings, homeassistant, hue_state);
+
+    let removed_topics: Vec<String> = published
+        .keys()
+        .filter(|topic| !configs.contains_key(*topic))
+        .cloned()
+        .collect();
+
+    for topic in removed_topics {
+        debug!("Clearing Home Assistant discovery config at {topic}");
+
+        mqtt_client
+            .client
+            .publish(&topic, rumqttc::QoS::AtLeastOnce, true, vec![])
+            .await?;
+
+        published.remove(&topic);
+    }
+
+    for (topic, config) in configs {
+        let json = serde_json::to_string(&config)?;
+
+        if published.get(&topic) == Some(&json) {
+            continue;
+        }
+
+        mqtt_client
+            .client
+            .publish(&topic, rumqttc::QoS::AtLeastOnce, true, json.clone())
+            .await?;
+
+        published.insert(topic, json);
+    }
+
+    Ok(())
+}
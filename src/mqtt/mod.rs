@@ -1,2 +1,10 @@
+pub mod availability;
+pub mod color;
+pub mod command_queue;
 pub mod events;
+pub mod homeassistant;
+pub mod mqtt_battery;
+pub mod mqtt_button_event;
 pub mod mqtt_device;
+pub mod mqtt_rotary_event;
+pub mod mqtt_scene;
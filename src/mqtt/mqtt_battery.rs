@@ -0,0 +1,71 @@
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{protocols::mqtt::MqttClient, settings::Settings};
+
+/// Battery state of a battery powered Hue device, such as a dimmer switch or
+/// a motion sensor
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct MqttBattery {
+    /// ID of the device that the battery belongs to
+    pub id: String,
+    pub name: String,
+
+    /// Battery level in percent (0 - 100)
+    pub battery_level: Option<u8>,
+
+    /// One of "normal", "low" or "critical"
+    pub battery_state: Option<String>,
+}
+
+pub async fn publish_mqtt_battery(
+    mqtt_client: &MqttClient,
+    settings: &Settings,
+    mqtt_battery: &MqttBattery,
+) -> Result<()> {
+    let topic = settings
+        .mqtt
+        .device_battery_topic
+        .replace("{id}", &mqtt_battery.id);
+
+    let json = serde_json::to_string(&mqtt_battery)?;
+
+    mqtt_client
+        .client
+        .publish(topic, rumqttc::QoS::AtLeastOnce, true, json)
+        .await?;
+
+    Ok(())
+}
+
+/// Clears the retained battery state of a device that has been removed from
+/// the Hue bridge
+pub async fn clear_mqtt_battery(
+    mqtt_client: &MqttClient,
+    settings: &Settings,
+    device_id: &str,
+) -> Result<()> {
+    let topic = settings
+        .mqtt
+        .device_battery_topic
+        .replace("{id}", device_id);
+
+    mqtt_client
+        .client
+        .publish(topic, rumqttc::QoS::AtLeastOnce, true, vec![])
+        .await?;
+
+    Ok(())
+}
+
+pub async fn publish_mqtt_batteries(
+    mqtt_client: &MqttClient,
+    settings: &Settings,
+    mqtt_batteries: Vec<MqttBattery>,
+) -> Result<()> {
+    for mqtt_battery in mqtt_batteries {
+        publish_mqtt_battery(mqtt_client, settings, &mqtt_battery).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{hue::rest::button::ButtonReport, protocols::mqtt::MqttClient, settings::Settings};
+
+use super::mqtt_device::MqttDevice;
+
+/// A single button event as reported by the Hue bridge
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct MqttButtonEvent {
+    pub id: String,
+    pub name: String,
+
+    /// One of "initial_press", "repeat", "long_press", "short_release" or
+    /// "long_release"
+    pub event: String,
+
+    /// Time at which the Hue bridge registered the event
+    pub updated: String,
+}
+
+impl MqttButtonEvent {
+    /// Returns a button event for the given button report, unless an event
+    /// with the same or a later timestamp has already been seen for the
+    /// button.
+    ///
+    /// Button reports arrive both through the eventsource API and through
+    /// polling, and may arrive out of order. Only ever moving forward in time
+    /// means that a delayed report may get dropped, but events are never
+    /// published out of order.
+    pub fn from_report(mqtt_device: &mut MqttDevice, report: &ButtonReport) -> Option<Self> {
+        // Ignore junk data
+        if report.updated == ButtonReport::default().updated {
+            return None;
+        }
+
+        if let Some(event_updated) = &mqtt_device.event_updated {
+            // Timestamps are in ISO 8601 format, so they can be compared as
+            // strings
+            if &report.updated <= event_updated {
+                return None;
+            }
+        }
+
+        mqtt_device.event_updated = Some(report.updated.clone());
+
+        Some(MqttButtonEvent {
+            id: mqtt_device.id.clone(),
+            name: mqtt_device.name.clone(),
+            event: report.event.clone(),
+            updated: report.updated.clone(),
+        })
+    }
+}
+
+pub async fn publish_mqtt_button_event(
+    mqtt_client: &MqttClient,
+    settings: &Settings,
+    mqtt_button_event: &MqttButtonEvent,
+) -> Result<()> {
+    let topic = settings
+        .mqtt
+        .button_event_topic
+        .replace("{id}", &mqtt_button_event.id);
+
+    let json = serde_json::to_string(&mqtt_button_event)?;
+
+    // Events are not retained, as they are only meaningful at the time they
+    // happen
+    mqtt_client
+        .client
+        .publish(topic, rumqttc::QoS::AtLeastOnce, false, json)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn publish_mqtt_button_events(
+    mqtt_client: &MqttClient,
+    settings: &Settings,
+    mqtt_button_events: Vec<MqttButtonEvent>,
+) -> Result<()> {
+    for mqtt_button_event in mqtt_button_events {
+        publish_mqtt_button_event(mqtt_client, settings, &mqtt_button_event).await?;
+    }
+
+    Ok(())
+}
@@ -1,7 +1,8 @@
 use color_eyre::Result;
 use derive_builder::Builder;
-use serde::{Deserialize, Serialize};
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
 
+use super::color::ColorSpaces;
 use crate::{protocols::mqtt::MqttClient, settings::Settings};
 
 #[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
@@ -11,6 +12,10 @@ pub struct Capabilities {
 
     /// Color temperature (2000 - 6500)
     pub ct: Option<std::ops::Range<u16>>,
+
+    /// Triangle in the XY color space containing the colors the light is able
+    /// to reproduce
+    pub gamut: Option<Gamut>,
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
@@ -19,16 +24,145 @@ pub struct Xy {
     pub y: f32,
 }
 
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Gamut {
+    pub red: Xy,
+    pub green: Xy,
+    pub blue: Xy,
+}
+
 #[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
 pub struct Ct {
     pub ct: u16,
 }
 
+/// Color temperature in Kelvin, same as `Ct`
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Kelvin {
+    pub kelvin: u16,
+}
+
+/// Color temperature in mireds
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Mired {
+    pub mired: u16,
+}
+
+fn default_hsv_value() -> f32 {
+    1.0
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Hsv {
+    /// Hue in degrees (0.0 - 360.0)
+    pub hue: f32,
+
+    /// Saturation (0.0 - 1.0)
+    pub saturation: f32,
+
+    /// Value (0.0 - 1.0), only used when converting to other color spaces.
+    /// Use `brightness` to set the brightness of a light.
+    #[serde(default = "default_hsv_value")]
+    pub value: f32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// Hex color code of format "#ff8800"
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Hex {
+    pub hex: String,
+}
+
+/// Colors published by us are always either `Xy` or `Ct`. The other variants
+/// are only accepted in set payloads, and are converted to `Xy` or `Ct` before
+/// sending them to the Hue bridge.
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum DeviceColor {
     Xy(Xy),
     Ct(Ct),
+    Kelvin(Kelvin),
+    Mired(Mired),
+    Hsv(Hsv),
+    Rgb(Rgb),
+    Hex(Hex),
+}
+
+/// Reading of a Hue sensor
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SensorValue {
+    /// Whether the button is pressed
+    Button(bool),
+
+    /// Whether motion is detected
+    Motion(bool),
+
+    /// Temperature in °C
+    Temperature(f64),
+
+    /// Light level as reported by Hue, 10000 * log10(lux) + 1
+    LightLevel(f64),
+}
+
+impl SensorValue {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SensorValue::Button(_) => "button",
+            SensorValue::Motion(_) => "motion",
+            SensorValue::Temperature(_) => "temperature",
+            SensorValue::LightLevel(_) => "light_level",
+        }
+    }
+
+    pub fn unit(&self) -> Option<&'static str> {
+        match self {
+            SensorValue::Button(_) | SensorValue::Motion(_) => None,
+            SensorValue::Temperature(_) => Some("°C"),
+            SensorValue::LightLevel(_) => Some("lx"),
+        }
+    }
+
+    /// Formats the sensor value as it was published by earlier versions of
+    /// hue-mqtt, i.e. "true", "21.4" or "13000"
+    pub fn to_legacy_string(self) -> String {
+        match self {
+            SensorValue::Button(value) | SensorValue::Motion(value) => value.to_string(),
+            SensorValue::Temperature(value) | SensorValue::LightLevel(value) => value.to_string(),
+        }
+    }
+}
+
+/// Converts a Hue light level to lux, rounded to one decimal
+fn light_level_to_lux(light_level: f64) -> f64 {
+    let lux = 10_f64.powf((light_level - 1.0) / 10000.0);
+
+    (lux * 10.0).round() / 10.0
+}
+
+impl Serialize for SensorValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("SensorValue", 3)?;
+        state.serialize_field("kind", self.kind())?;
+
+        match self {
+            SensorValue::Button(value) | SensorValue::Motion(value) => {
+                state.serialize_field("value", value)?
+            }
+            SensorValue::Temperature(value) => state.serialize_field("value", value)?,
+            SensorValue::LightLevel(value) => {
+                state.serialize_field("value", &light_level_to_lux(*value))?
+            }
+        }
+
+        state.serialize_field("unit", &self.unit())?;
+        state.end()
+    }
 }
 
 #[derive(Builder, Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
@@ -40,27 +174,155 @@ pub struct MqttDevice {
     pub brightness: Option<f32>,
     pub color: Option<DeviceColor>,
     pub transition_ms: Option<f32>,
-    pub sensor_value: Option<String>,
+
+    /// Relative brightness change (-1.0 - 1.0), only used in set payloads
+    #[serde(skip_serializing)]
+    pub brightness_step: Option<f32>,
+
+    /// Relative color temperature change in mireds, positive values make the
+    /// light warmer. Only used in set payloads.
+    #[serde(skip_serializing)]
+    pub ct_step: Option<i32>,
+
+    /// Toggles the power state of the light, only used in set payloads
+    #[serde(default, skip_serializing)]
+    pub toggle: bool,
+
+    /// Serialized through `MqttDevicePayload`, as the format depends on the
+    /// `legacy_sensor_value` setting
+    #[serde(skip_serializing, skip_deserializing)]
+    pub sensor_value: Option<SensorValue>,
+
     pub capabilities: Option<Capabilities>,
 
     #[serde(skip_serializing, skip_deserializing)]
     pub updated: Option<String>,
+
+    /// Timestamp of the latest button event published to the button event
+    /// topic
+    #[serde(skip_serializing, skip_deserializing)]
+    pub event_updated: Option<String>,
+
+    /// Whether this device is a Hue room or zone, controlled through its
+    /// grouped_light resource
+    #[serde(skip_serializing, skip_deserializing)]
+    pub is_group: bool,
 }
 
-pub async fn publish_mqtt_device(
-    mqtt_client: &MqttClient,
-    settings: &Settings,
-    mqtt_device: &MqttDevice,
-) -> Result<()> {
-    let topic_template = if mqtt_device.sensor_value.is_some() {
+impl MqttDevice {
+    /// Merges a newer set payload for the same device into this one.
+    ///
+    /// Fields set in the newer payload replace those of this one, while fields
+    /// left unset are kept. Relative changes are applied on top of absolute
+    /// values set by this payload where possible, so that the merged payload
+    /// results in the same light state as sending both payloads in order. The
+    /// transition time is always taken from the newer payload.
+    pub fn merge(&mut self, newer: MqttDevice) {
+        if newer.power.is_some() {
+            self.power = newer.power;
+            self.toggle = false;
+        } else if newer.toggle {
+            match self.power {
+                Some(power) => self.power = Some(!power),
+                // Two toggles cancel each other out
+                None => self.toggle = !self.toggle,
+            }
+        }
+
+        if newer.brightness.is_some() {
+            self.brightness = newer.brightness;
+            self.brightness_step = None;
+        } else if let Some(step) = newer.brightness_step {
+            match self.brightness {
+                Some(brightness) => self.brightness = Some((brightness + step).clamp(0.0, 1.0)),
+                None => self.brightness_step = Some(self.brightness_step.unwrap_or(0.0) + step),
+            }
+        }
+
+        if newer.color.is_some() {
+            self.color = newer.color;
+            self.ct_step = None;
+        } else if let Some(step) = newer.ct_step {
+            match &self.color {
+                // Limiting to the range of the light is left to
+                // `process_next_mqtt_message`, which knows the light's
+                // capabilities
+                Some(DeviceColor::Ct(Ct { ct })) => {
+                    let mired = ((1_000_000.0 / (*ct).max(1) as f32).round() as i32 + step).max(1);
+                    self.color = Some(DeviceColor::Ct(Ct {
+                        ct: (1_000_000.0 / mired as f32).round() as u16,
+                    }));
+                }
+                _ => self.ct_step = Some(self.ct_step.unwrap_or(0) + step),
+            }
+        }
+
+        self.transition_ms = newer.transition_ms;
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+enum SensorValuePayload {
+    Typed(SensorValue),
+    Legacy(String),
+}
+
+/// Payload of messages published to the light, sensor and group topics
+#[derive(Serialize, Debug, Clone)]
+struct MqttDevicePayload<'a> {
+    #[serde(flatten)]
+    mqtt_device: &'a MqttDevice,
+    sensor_value: Option<SensorValuePayload>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color_spaces: Option<ColorSpaces>,
+}
+
+impl<'a> MqttDevicePayload<'a> {
+    fn new(settings: &Settings, mqtt_device: &'a MqttDevice) -> Self {
+        let sensor_value = mqtt_device.sensor_value.map(|sensor_value| {
+            if settings.mqtt.legacy_sensor_value {
+                SensorValuePayload::Legacy(sensor_value.to_legacy_string())
+            } else {
+                SensorValuePayload::Typed(sensor_value)
+            }
+        });
+
+        let color_spaces = if settings.mqtt.publish_color_spaces {
+            mqtt_device.color.as_ref().and_then(ColorSpaces::new)
+        } else {
+            None
+        };
+
+        MqttDevicePayload {
+            mqtt_device,
+            sensor_value,
+            color_spaces,
+        }
+    }
+}
+
+fn mqtt_device_topic(settings: &Settings, mqtt_device: &MqttDevice) -> String {
+    let topic_template = if mqtt_device.is_group {
+        &settings.mqtt.group_topic
+    } else if mqtt_device.sensor_value.is_some() {
         &settings.mqtt.sensor_topic
     } else {
         &settings.mqtt.light_topic
     };
 
-    let topic = topic_template.replace("{id}", &mqtt_device.id);
+    topic_template.replace("{id}", &mqtt_device.id)
+}
 
-    let json = serde_json::to_string(&mqtt_device)?;
+pub async fn publish_mqtt_device(
+    mqtt_client: &MqttClient,
+    settings: &Settings,
+    mqtt_device: &MqttDevice,
+) -> Result<()> {
+    let topic = mqtt_device_topic(settings, mqtt_device);
+
+    let json = serde_json::to_string(&MqttDevicePayload::new(settings, mqtt_device))?;
 
     mqtt_client
         .client
@@ -70,6 +332,23 @@ pub async fn publish_mqtt_device(
     Ok(())
 }
 
+/// Clears the retained state of a device that has been removed from the Hue
+/// bridge
+pub async fn clear_mqtt_device(
+    mqtt_client: &MqttClient,
+    settings: &Settings,
+    mqtt_device: &MqttDevice,
+) -> Result<()> {
+    let topic = mqtt_device_topic(settings, mqtt_device);
+
+    mqtt_client
+        .client
+        .publish(topic, rumqttc::QoS::AtLeastOnce, true, vec![])
+        .await?;
+
+    Ok(())
+}
+
 pub async fn publish_mqtt_devices(
     mqtt_client: &MqttClient,
     settings: &Settings,
@@ -81,3 +360,155 @@ pub async fn publish_mqtt_devices(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(json: &str) -> MqttDevice {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn merged(older: &str, newer: &str) -> MqttDevice {
+        let mut merged = set(older);
+        merged.merge(set(newer));
+        merged
+    }
+
+    #[test]
+    fn keeps_fields_from_both_payloads() {
+        let merged = merged(
+            r#"{"id": "1", "name": "Lamp", "power": true}"#,
+            r#"{"id": "1", "name": "Lamp", "brightness": 0.3}"#,
+        );
+
+        assert_eq!(merged.power, Some(true));
+        assert_eq!(merged.brightness, Some(0.3));
+    }
+
+    #[test]
+    fn newer_fields_win() {
+        let merged = merged(
+            r#"{"id": "1", "name": "Lamp", "power": true, "brightness": 0.3, "color": {"ct": 2700}}"#,
+            r#"{"id": "1", "name": "Lamp", "power": false, "brightness": 0.8}"#,
+        );
+
+        assert_eq!(merged.power, Some(false));
+        assert_eq!(merged.brightness, Some(0.8));
+        assert_eq!(merged.color, Some(DeviceColor::Ct(Ct { ct: 2700 })));
+    }
+
+    #[test]
+    fn transition_is_taken_from_newer_payload() {
+        let merged1 = merged(
+            r#"{"id": "1", "name": "Lamp", "power": true, "transition_ms": 1000}"#,
+            r#"{"id": "1", "name": "Lamp", "brightness": 0.3}"#,
+        );
+        let merged2 = merged(
+            r#"{"id": "1", "name": "Lamp", "power": true}"#,
+            r#"{"id": "1", "name": "Lamp", "brightness": 0.3, "transition_ms": 400}"#,
+        );
+
+        assert_eq!(merged1.transition_ms, None);
+        assert_eq!(merged2.transition_ms, Some(400.0));
+    }
+
+    #[test]
+    fn brightness_steps_are_applied_to_brightness() {
+        let merged = merged(
+            r#"{"id": "1", "name": "Lamp", "brightness": 0.5}"#,
+            r#"{"id": "1", "name": "Lamp", "brightness_step": 0.2}"#,
+        );
+
+        assert_eq!(merged.brightness, Some(0.7));
+        assert_eq!(merged.brightness_step, None);
+    }
+
+    #[test]
+    fn brightness_steps_accumulate() {
+        let merged = merged(
+            r#"{"id": "1", "name": "Lamp", "brightness_step": 0.25}"#,
+            r#"{"id": "1", "name": "Lamp", "brightness_step": -0.5}"#,
+        );
+
+        assert_eq!(merged.brightness, None);
+        assert_eq!(merged.brightness_step, Some(-0.25));
+    }
+
+    #[test]
+    fn brightness_replaces_brightness_step() {
+        let merged = merged(
+            r#"{"id": "1", "name": "Lamp", "brightness_step": 0.1}"#,
+            r#"{"id": "1", "name": "Lamp", "brightness": 0.4}"#,
+        );
+
+        assert_eq!(merged.brightness, Some(0.4));
+        assert_eq!(merged.brightness_step, None);
+    }
+
+    #[test]
+    fn ct_steps_are_applied_to_color_temperature() {
+        let merged = merged(
+            r#"{"id": "1", "name": "Lamp", "color": {"ct": 4000}}"#,
+            r#"{"id": "1", "name": "Lamp", "ct_step": 50}"#,
+        );
+
+        assert_eq!(merged.color, Some(DeviceColor::Ct(Ct { ct: 3333 })));
+        assert_eq!(merged.ct_step, None);
+    }
+
+    #[test]
+    fn ct_steps_are_not_limited_to_a_fixed_range() {
+        let merged = merged(
+            r#"{"id": "1", "name": "Lamp", "color": {"ct": 2500}}"#,
+            r#"{"id": "1", "name": "Lamp", "ct_step": 200}"#,
+        );
+
+        // 600 mired, beyond what most Hue lights support
+        assert_eq!(merged.color, Some(DeviceColor::Ct(Ct { ct: 1667 })));
+    }
+
+    #[test]
+    fn color_replaces_ct_step() {
+        let merged = merged(
+            r#"{"id": "1", "name": "Lamp", "ct_step": 50}"#,
+            r#"{"id": "1", "name": "Lamp", "color": {"x": 0.3, "y": 0.3}}"#,
+        );
+
+        assert_eq!(merged.color, Some(DeviceColor::Xy(Xy { x: 0.3, y: 0.3 })));
+        assert_eq!(merged.ct_step, None);
+    }
+
+    #[test]
+    fn toggle_inverts_power() {
+        let merged = merged(
+            r#"{"id": "1", "name": "Lamp", "power": true}"#,
+            r#"{"id": "1", "name": "Lamp", "toggle": true}"#,
+        );
+
+        assert_eq!(merged.power, Some(false));
+        assert!(!merged.toggle);
+    }
+
+    #[test]
+    fn toggles_cancel_out() {
+        let merged = merged(
+            r#"{"id": "1", "name": "Lamp", "toggle": true}"#,
+            r#"{"id": "1", "name": "Lamp", "toggle": true}"#,
+        );
+
+        assert_eq!(merged.power, None);
+        assert!(!merged.toggle);
+    }
+
+    #[test]
+    fn power_replaces_toggle() {
+        let merged = merged(
+            r#"{"id": "1", "name": "Lamp", "toggle": true}"#,
+            r#"{"id": "1", "name": "Lamp", "power": true}"#,
+        );
+
+        assert_eq!(merged.power, Some(true));
+        assert!(!merged.toggle);
+    }
+}
@@ -0,0 +1,101 @@
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    hue::rest::relative_rotary::{RelativeRotaryEventData, RotationData},
+    protocols::mqtt::MqttClient,
+    settings::Settings,
+};
+
+/// A rotation of a relative rotary device, such as the Hue Tap Dial
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct MqttRotaryEvent {
+    pub id: String,
+    pub name: String,
+
+    /// One of "start" or "repeat"
+    pub action: String,
+
+    /// One of "clock_wise" or "counter_clock_wise"
+    pub direction: String,
+
+    /// Amount of rotation since the previous event
+    pub steps: u32,
+
+    /// Duration of the rotation since the start of the turn, in ms
+    pub duration: u32,
+
+    /// Time at which the Hue bridge registered the rotation, if reported
+    pub updated: Option<String>,
+}
+
+impl MqttRotaryEvent {
+    pub fn new(id: &str, name: &str, relative_rotary: &RelativeRotaryEventData) -> Option<Self> {
+        // Prefer rotary_report, as it is the only one carrying a timestamp
+        let (action, rotation, updated) = match relative_rotary {
+            RelativeRotaryEventData {
+                rotary_report: Some(report),
+                ..
+            } => (
+                &report.action,
+                &report.rotation,
+                Some(report.updated.clone()),
+            ),
+            RelativeRotaryEventData {
+                last_event: Some(last_event),
+                ..
+            } => (&last_event.action, &last_event.rotation, None),
+            _ => return None,
+        };
+
+        let RotationData {
+            direction,
+            steps,
+            duration,
+        } = rotation.clone();
+
+        Some(MqttRotaryEvent {
+            id: id.to_string(),
+            name: name.to_string(),
+            action: action.clone(),
+            direction,
+            steps,
+            duration,
+            updated,
+        })
+    }
+}
+
+pub async fn publish_mqtt_rotary_event(
+    mqtt_client: &MqttClient,
+    settings: &Settings,
+    mqtt_rotary_event: &MqttRotaryEvent,
+) -> Result<()> {
+    let topic = settings
+        .mqtt
+        .rotary_topic
+        .replace("{id}", &mqtt_rotary_event.id);
+
+    let json = serde_json::to_string(&mqtt_rotary_event)?;
+
+    // Rotations are not retained, as they are only meaningful at the time
+    // they happen
+    mqtt_client
+        .client
+        .publish(topic, rumqttc::QoS::AtLeastOnce, false, json)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn publish_mqtt_rotary_events(
+    mqtt_client: &MqttClient,
+    settings: &Settings,
+    mqtt_rotary_events: Vec<MqttRotaryEvent>,
+) -> Result<()> {
+    for mqtt_rotary_event in mqtt_rotary_events {
+        publish_mqtt_rotary_event(mqtt_client, settings, &mqtt_rotary_event).await?;
+    }
+
+    Ok(())
+}
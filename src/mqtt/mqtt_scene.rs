@@ -0,0 +1,95 @@
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    hue::rest::{
+        light::DimmingData,
+        scene::{SceneRecall, SceneRecallAction},
+    },
+    protocols::mqtt::MqttClient,
+    settings::Settings,
+};
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct MqttScene {
+    pub id: String,
+    pub name: String,
+
+    /// ID of the room or zone that the scene belongs to
+    pub group: String,
+    pub group_name: Option<String>,
+
+    /// One of "inactive", "static" or "dynamic_palette"
+    pub status: Option<String>,
+}
+
+/// Payload of messages published to the scene recall topic
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct MqttSceneRecall {
+    #[serde(default)]
+    pub action: SceneRecallAction,
+
+    /// Transition time measured in ms
+    pub duration: Option<u32>,
+
+    /// Brightness to recall the scene at (0.0 - 1.0)
+    pub dimming: Option<f32>,
+}
+
+impl From<MqttSceneRecall> for SceneRecall {
+    fn from(recall: MqttSceneRecall) -> Self {
+        SceneRecall {
+            action: recall.action,
+            duration: recall.duration,
+            dimming: recall.dimming.map(|dimming| DimmingData {
+                brightness: dimming * 100.0,
+            }),
+        }
+    }
+}
+
+pub async fn publish_mqtt_scene(
+    mqtt_client: &MqttClient,
+    settings: &Settings,
+    mqtt_scene: &MqttScene,
+) -> Result<()> {
+    let topic = settings.mqtt.scene_topic.replace("{id}", &mqtt_scene.id);
+
+    let json = serde_json::to_string(&mqtt_scene)?;
+
+    mqtt_client
+        .client
+        .publish(topic, rumqttc::QoS::AtLeastOnce, true, json)
+        .await?;
+
+    Ok(())
+}
+
+/// Clears the retained state of a scene that has been removed from the Hue
+/// bridge
+pub async fn clear_mqtt_scene(
+    mqtt_client: &MqttClient,
+    settings: &Settings,
+    mqtt_scene: &MqttScene,
+) -> Result<()> {
+    let topic = settings.mqtt.scene_topic.replace("{id}", &mqtt_scene.id);
+
+    mqtt_client
+        .client
+        .publish(topic, rumqttc::QoS::AtLeastOnce, true, vec![])
+        .await?;
+
+    Ok(())
+}
+
+pub async fn publish_mqtt_scenes(
+    mqtt_client: &MqttClient,
+    settings: &Settings,
+    mqtt_scenes: Vec<MqttScene>,
+) -> Result<()> {
+    for mqtt_scene in mqtt_scenes {
+        publish_mqtt_scene(mqtt_client, settings, &mqtt_scene).await?;
+    }
+
+    Ok(())
+}
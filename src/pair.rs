@@ -0,0 +1,149 @@
+use std::{path::Path, time::Duration};
+
+use color_eyre::Result;
+use eyre::OptionExt;
+use hyper::Request;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use toml_edit::{value, DocumentMut, Item, Table};
+
+use crate::{
+    hue::discovery::resolve_bridge_addr, protocols::https::mk_hyper_https_client,
+    settings::HueSettings,
+};
+
+/// Identifies hue-mqtt in the Hue app's list of connected apps
+const DEVICETYPE: &str = "hue-mqtt#hue-mqtt";
+
+/// Error type returned by the bridge until its link button has been pressed
+const LINK_BUTTON_NOT_PRESSED: u32 = 101;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait for the link button to be pressed before giving up
+const PAIRING_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Serialize, Debug)]
+struct PairRequest<'a> {
+    devicetype: &'a str,
+    generateclientkey: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct PairSuccess {
+    username: String,
+    clientkey: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PairError {
+    #[serde(rename = "type")]
+    error_type: u32,
+    description: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum PairResponse {
+    Success(PairSuccess),
+    Error(PairError),
+}
+
+/// Reads the `[hue_bridge]` section of the settings file. Unlike
+/// `read_settings`, this does not require the rest of the settings to be
+/// filled in yet.
+fn read_hue_settings(settings_file: &Path) -> Result<HueSettings> {
+    let hue_settings = config::Config::builder()
+        .add_source(config::File::from(settings_file))
+        .build()?
+        .get::<HueSettings>("hue_bridge")?;
+
+    Ok(hue_settings)
+}
+
+/// Writes the app key and client key into the `[hue_bridge]` section of the
+/// settings file, keeping the rest of the file as is
+fn write_keys(settings_file: &Path, pair_success: &PairSuccess) -> Result<()> {
+    let contents = std::fs::read_to_string(settings_file)?;
+    let mut document: DocumentMut = contents.parse()?;
+
+    let hue_bridge = document
+        .entry("hue_bridge")
+        .or_insert(Item::Table(Table::new()))
+        .as_table_mut()
+        .ok_or_eyre("[hue_bridge] in settings is not a table")?;
+
+    hue_bridge["appkey"] = value(&pair_success.username);
+
+    if let Some(clientkey) = &pair_success.clientkey {
+        hue_bridge["clientkey"] = value(clientkey);
+    }
+
+    std::fs::write(settings_file, document.to_string())?;
+
+    Ok(())
+}
+
+/// Creates an app key on the Hue bridge configured in `settings_file`, and
+/// writes it back into the file. The bridge only hands out app keys for a
+/// while after its link button has been pressed, so keep asking until that
+/// happens.
+pub async fn pair(settings_file: &Path) -> Result<()> {
+    let mut hue_settings = read_hue_settings(settings_file)?;
+    resolve_bridge_addr(&mut hue_settings).await?;
+
+    let client = mk_hyper_https_client(&hue_settings)?;
+
+    let uri: hyper::Uri = format!("https://{}/api", hue_settings.addr).parse()?;
+    let body = serde_json::to_string(&PairRequest {
+        devicetype: DEVICETYPE,
+        generateclientkey: true,
+    })?;
+
+    let started = Instant::now();
+    let mut prompted = false;
+
+    let pair_success = loop {
+        let request = Request::builder()
+            .method("POST")
+            .uri(&uri)
+            .body(body.clone().into())?;
+
+        let result = client.request(request).await?;
+        let body_bytes = hyper::body::to_bytes(result.into_body()).await?;
+        let de = &mut serde_json::Deserializer::from_slice(&body_bytes);
+        let response: Vec<PairResponse> = serde_path_to_error::deserialize(de)?;
+
+        match response.into_iter().next() {
+            Some(PairResponse::Success(pair_success)) => break pair_success,
+            Some(PairResponse::Error(e)) if e.error_type == LINK_BUTTON_NOT_PRESSED => {
+                if !prompted {
+                    eprintln!(
+                        "Press the link button on the Hue bridge at {}",
+                        hue_settings.addr
+                    );
+                    prompted = true;
+                }
+            }
+            Some(PairResponse::Error(e)) => {
+                return Err(eyre!("Hue bridge refused pairing: {}", e.description))
+            }
+            None => return Err(eyre!("Empty response from Hue bridge")),
+        }
+
+        if started.elapsed() > PAIRING_TIMEOUT {
+            return Err(eyre!("Timed out waiting for the link button to be pressed"));
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    };
+
+    write_keys(settings_file, &pair_success)?;
+
+    eprintln!(
+        "Paired with the Hue bridge, app key written to {}",
+        settings_file.display()
+    );
+
+    Ok(())
+}
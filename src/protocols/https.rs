@@ -1,12 +1,147 @@
+use std::{
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
 use color_eyre::Result;
-use hyper::{Request, Uri};
+use hyper::{
+    client::connect::dns::{GaiResolver, Name},
+    service::Service,
+    Request, Uri,
+};
 use serde::{Deserialize, Serialize};
 use tokio_rustls::rustls::{ClientConfig, RootCertStore};
 
-use crate::settings::Settings;
+use crate::{
+    capture::{capture_get, capture_put},
+    hue::discovery::discovered_bridge,
+    settings::{HueSettings, Settings},
+};
 
 pub type HyperHttpsClient =
-    hyper::Client<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>;
+    hyper::Client<hyper_rustls::HttpsConnector<hyper::client::HttpConnector<BridgeResolver>>>;
+
+/// Resolves the ID of a Hue bridge found through mDNS to its current address,
+/// and other host names through DNS
+#[derive(Clone)]
+pub struct BridgeResolver {
+    gai: GaiResolver,
+}
+
+impl BridgeResolver {
+    fn new() -> Self {
+        BridgeResolver {
+            gai: GaiResolver::new(),
+        }
+    }
+}
+
+type ResolveFuture =
+    Pin<Box<dyn Future<Output = Result<std::vec::IntoIter<SocketAddr>, std::io::Error>> + Send>>;
+
+impl Service<Name> for BridgeResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = std::io::Error;
+    type Future = ResolveFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.gai.poll_ready(cx)
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        if let Some(bridge) = discovered_bridge() {
+            if name.as_str().eq_ignore_ascii_case(&bridge.id) {
+                return Box::pin(async move { Ok(vec![bridge.addr].into_iter()) });
+            }
+        }
+
+        let future = self.gai.call(name);
+
+        Box::pin(async move { Ok(future.await?.collect::<Vec<_>>().into_iter()) })
+    }
+}
+
+static SUPPORTED_SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+];
+
+/// Hue bridge certificates have the bridge ID as their common name, and no
+/// subject alternative names that would allow verifying them against a host
+/// name. Verifies that the certificate is signed by the trusted CA, and that
+/// its common name matches the bridge ID used as the server name.
+struct BridgeIdVerification {
+    ca_cert: Vec<u8>,
+}
+
+impl BridgeIdVerification {
+    fn new(ca_cert: Vec<u8>) -> std::sync::Arc<Self> {
+        std::sync::Arc::new(Self { ca_cert })
+    }
+}
+
+fn tls_error(e: impl std::fmt::Debug) -> tokio_rustls::rustls::Error {
+    tokio_rustls::rustls::Error::General(format!("{e:?}"))
+}
+
+impl tokio_rustls::rustls::client::ServerCertVerifier for BridgeIdVerification {
+    fn verify_server_cert(
+        &self,
+        end_entity: &tokio_rustls::rustls::Certificate,
+        intermediates: &[tokio_rustls::rustls::Certificate],
+        server_name: &tokio_rustls::rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        now: std::time::SystemTime,
+    ) -> Result<tokio_rustls::rustls::client::ServerCertVerified, tokio_rustls::rustls::Error> {
+        let tokio_rustls::rustls::ServerName::DnsName(bridge_id) = server_name else {
+            return Err(tls_error("Expected Hue bridge ID as server name"));
+        };
+
+        let trust_anchor =
+            webpki::TrustAnchor::try_from_cert_der(&self.ca_cert).map_err(tls_error)?;
+        let intermediates: Vec<&[u8]> = intermediates.iter().map(|cert| cert.0.as_ref()).collect();
+
+        webpki::EndEntityCert::try_from(end_entity.0.as_ref())
+            .and_then(|cert| {
+                cert.verify_for_usage(
+                    SUPPORTED_SIG_ALGS,
+                    &[trust_anchor],
+                    &intermediates,
+                    webpki::Time::try_from(now).map_err(|_| webpki::Error::BadDerTime)?,
+                    webpki::KeyUsage::server_auth(),
+                    &[],
+                )
+            })
+            .map_err(tls_error)?;
+
+        let (_, cert) = x509_parser::parse_x509_certificate(&end_entity.0).map_err(tls_error)?;
+
+        let common_name = cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|common_name| common_name.as_str().ok());
+
+        match common_name {
+            Some(common_name) if common_name.eq_ignore_ascii_case(bridge_id.as_ref()) => {
+                Ok(tokio_rustls::rustls::client::ServerCertVerified::assertion())
+            }
+            _ => Err(tls_error(format!(
+                "Certificate common name {common_name:?} does not match Hue bridge ID {}",
+                bridge_id.as_ref()
+            ))),
+        }
+    }
+}
 
 // see https://quinn-rs.github.io/quinn/quinn/certificate.html
 struct SkipServerVerification;
@@ -31,9 +166,9 @@ impl tokio_rustls::rustls::client::ServerCertVerifier for SkipServerVerification
     }
 }
 
-pub fn mk_hyper_https_client(settings: &Settings) -> Result<HyperHttpsClient> {
+pub fn mk_hyper_https_client(hue_settings: &HueSettings) -> Result<HyperHttpsClient> {
     // https://github.com/spietika/restson-rust/pull/20
-    let mut http = hyper::client::HttpConnector::new();
+    let mut http = hyper::client::HttpConnector::new_with_resolver(BridgeResolver::new());
     http.enforce_http(false);
 
     // This is the Signify CA certificate for Hue bridges, from:
@@ -41,7 +176,7 @@ pub fn mk_hyper_https_client(settings: &Settings) -> Result<HyperHttpsClient> {
     const HUE_CA_CERT: &[u8] = include_bytes!("hue_ca_cert.pem");
 
     // Allow overriding the trusted CA certificate for older bridge firmware that still use self signed certs
-    let cert_bytes = match &settings.hue_bridge.self_signed_cert {
+    let cert_bytes = match &hue_settings.self_signed_cert {
         Some(cert) => cert.as_bytes().to_vec(),
         None => HUE_CA_CERT.to_vec(),
     };
@@ -62,14 +197,18 @@ pub fn mk_hyper_https_client(settings: &Settings) -> Result<HyperHttpsClient> {
 
     // Allow disabling host name verification
     // See https://docs.rs/native-tls/0.2.2/native_tls/struct.TlsConnectorBuilder.html#method.danger_accept_invalid_hostnames
-    if let Some(true) = settings.hue_bridge.disable_host_name_verification {
+    if let Some(true) = hue_settings.disable_host_name_verification {
         client_config
             .dangerous()
             .set_certificate_verifier(SkipServerVerification::new());
+    } else if discovered_bridge().is_some() {
+        // The bridge is addressed by its ID, see `resolve_bridge_addr`
+        client_config
+            .dangerous()
+            .set_certificate_verifier(BridgeIdVerification::new(certificate.0.clone()));
     }
 
-    let https =
-        hyper_rustls::HttpsConnector::<hyper::client::HttpConnector>::from((http, client_config));
+    let https = hyper_rustls::HttpsConnector::from((http, client_config));
 
     // Build the hyper client
     let client = hyper::Client::builder().build(https);
@@ -89,7 +228,10 @@ pub async fn mk_get_request<T: for<'a> Deserialize<'a>>(
         .body(hyper::Body::empty())?;
 
     let result = client.request(request).await?;
+    let status = result.status();
     let body_bytes = hyper::body::to_bytes(result.into_body()).await?;
+    capture_get(uri, status, &body_bytes);
+
     let de = &mut serde_json::Deserializer::from_slice(&body_bytes);
     let response: T = serde_path_to_error::deserialize(de)?;
 
@@ -112,10 +254,13 @@ where
         .method("PUT")
         .header("hue-application-key", &settings.hue_bridge.appkey)
         .uri(uri)
-        .body(body.into())?;
+        .body(body.clone().into())?;
 
     let result = client.request(request).await?;
+    let status = result.status();
     let body_bytes = hyper::body::to_bytes(result.into_body()).await?;
+    capture_put(uri, &body, status, &body_bytes);
+
     let de = &mut serde_json::Deserializer::from_slice(&body_bytes);
     let response: ResponseBody = serde_path_to_error::deserialize(de)?;
 
@@ -2,25 +2,98 @@
 
 use color_eyre::Result;
 use rand::{distributions::Alphanumeric, Rng};
-use rumqttc::{AsyncClient, MqttOptions};
-use std::{collections::VecDeque, sync::Arc, time::Duration};
-use tokio::{
-    sync::{Notify, RwLock},
-    task,
+use rumqttc::{
+    tokio_rustls::rustls::{
+        pki_types::{
+            CertificateDer, PrivateKeyDer, PrivatePkcs1KeyDer, PrivatePkcs8KeyDer,
+            PrivateSec1KeyDer,
+        },
+        ClientConfig, RootCertStore,
+    },
+    AsyncClient, MqttOptions, TlsConfiguration, Transport,
 };
+use std::{sync::Arc, time::Duration};
+use tokio::{sync::RwLock, task};
 
 use crate::{
-    mqtt::{events::handle_incoming_mqtt_event, mqtt_device::MqttDevice},
-    settings::Settings,
+    mqtt::{
+        availability::mk_status_last_will, command_queue::CommandQueue,
+        events::handle_incoming_mqtt_event,
+    },
+    settings::{MqttTransport, Settings},
 };
 
-type UnhandledMessages = Arc<RwLock<VecDeque<MqttDevice>>>;
-
 #[derive(Clone)]
 pub struct MqttClient {
     pub client: AsyncClient,
-    pub unhandled_messages: UnhandledMessages,
-    pub notify: Arc<Notify>,
+    pub command_queue: CommandQueue,
+
+    /// Whether we are currently connected to the MQTT broker
+    pub connected: Arc<RwLock<bool>>,
+}
+
+fn parse_pem_certs(pem: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut pem.as_bytes())?;
+
+    if certs.is_empty() {
+        return Err(eyre!("Failed to parse certificate"));
+    }
+
+    Ok(certs.into_iter().map(CertificateDer::from).collect())
+}
+
+fn parse_pem_key(pem: &str) -> Result<PrivateKeyDer<'static>> {
+    match rustls_pemfile::read_one(&mut pem.as_bytes())? {
+        Some(rustls_pemfile::Item::RSAKey(key)) => Ok(PrivatePkcs1KeyDer::from(key).into()),
+        Some(rustls_pemfile::Item::PKCS8Key(key)) => Ok(PrivatePkcs8KeyDer::from(key).into()),
+        Some(rustls_pemfile::Item::ECKey(key)) => Ok(PrivateSec1KeyDer::from(key).into()),
+        _ => Err(eyre!("Failed to parse private key")),
+    }
+}
+
+fn mk_mqtt_tls_config(settings: &Settings) -> Result<TlsConfiguration> {
+    let mut root_store = RootCertStore::empty();
+
+    // Trust the configured CA certificate, or fall back to the system's root
+    // certificates
+    match &settings.mqtt.ca_cert {
+        Some(ca_cert) => {
+            for cert in parse_pem_certs(ca_cert)? {
+                root_store.add(cert)?;
+            }
+        }
+        None => {
+            // Skip certificates that fail to parse instead of giving up on
+            // all of them, some systems ship a few that rustls rejects
+            let (added, ignored) =
+                root_store.add_parsable_certificates(rustls_native_certs::load_native_certs()?);
+
+            if added == 0 {
+                return Err(eyre!(
+                    "No usable root certificates found on the system ({ignored} ignored)"
+                ));
+            }
+
+            if ignored > 0 {
+                debug!("Ignored {ignored} unparsable system root certificates");
+            }
+        }
+    }
+
+    let builder = ClientConfig::builder().with_root_certificates(root_store);
+
+    let client_config = match (&settings.mqtt.client_cert, &settings.mqtt.client_key) {
+        (Some(client_cert), Some(client_key)) => builder
+            .with_client_auth_cert(parse_pem_certs(client_cert)?, parse_pem_key(client_key)?)?,
+        (None, None) => builder.with_no_client_auth(),
+        _ => {
+            return Err(eyre!(
+                "Both client_cert and client_key need to be set for MQTT client authentication"
+            ))
+        }
+    };
+
+    Ok(TlsConfiguration::Rustls(Arc::new(client_config)))
 }
 
 pub async fn mk_mqtt_client(settings: &Settings) -> Result<MqttClient> {
@@ -30,21 +103,50 @@ pub async fn mk_mqtt_client(settings: &Settings) -> Result<MqttClient> {
         .map(char::from)
         .collect();
 
+    // Websocket transports expect the broker address to be a full URL
+    let ws_path = settings.mqtt.ws_path.as_deref().unwrap_or("/mqtt");
+    let broker_addr = match settings.mqtt.transport {
+        MqttTransport::Tcp | MqttTransport::Tls => settings.mqtt.host.clone(),
+        MqttTransport::Ws => format!(
+            "ws://{}:{}{}",
+            settings.mqtt.host, settings.mqtt.port, ws_path
+        ),
+        MqttTransport::Wss => format!(
+            "wss://{}:{}{}",
+            settings.mqtt.host, settings.mqtt.port, ws_path
+        ),
+    };
+
     let mut options = MqttOptions::new(
         format!("{}-{}", settings.mqtt.id, random_string),
-        settings.mqtt.host.clone(),
+        broker_addr,
         settings.mqtt.port,
     );
     options.set_keep_alive(Duration::from_secs(5));
-    let (client, mut eventloop) = AsyncClient::new(options, 10);
+    options.set_last_will(mk_status_last_will(settings));
+
+    if let Some(username) = &settings.mqtt.username {
+        options.set_credentials(username, settings.mqtt.password.clone().unwrap_or_default());
+    }
 
-    let unhandled_messages: UnhandledMessages = Default::default();
-    let notify = Arc::new(Notify::new());
+    match settings.mqtt.transport {
+        MqttTransport::Tcp => {}
+        MqttTransport::Tls => {
+            options.set_transport(Transport::Tls(mk_mqtt_tls_config(settings)?));
+        }
+        MqttTransport::Ws => {
+            options.set_transport(Transport::Ws);
+        }
+        MqttTransport::Wss => {
+            options.set_transport(Transport::Wss(mk_mqtt_tls_config(settings)?));
+        }
+    }
+    let (client, mut eventloop) = AsyncClient::new(options, 10);
 
     let mqtt_client = MqttClient {
         client,
-        unhandled_messages,
-        notify,
+        command_queue: CommandQueue::new(settings),
+        connected: Default::default(),
     };
 
     {
@@ -55,6 +157,10 @@ pub async fn mk_mqtt_client(settings: &Settings) -> Result<MqttClient> {
             loop {
                 let notification = eventloop.poll().await;
 
+                if notification.is_err() {
+                    *mqtt_client.connected.write().await = false;
+                }
+
                 let res = (|| async {
                     handle_incoming_mqtt_event(notification?, &mqtt_client, &settings).await?;
 
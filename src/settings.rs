@@ -1,12 +1,146 @@
 use serde::Deserialize;
 
+/// Value of `hue_bridge.addr` that enables finding the bridge through mDNS
+pub const AUTO_ADDR: &str = "auto";
+
+fn default_command_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_command_retries() -> u32 {
+    3
+}
+
+fn default_commands_per_second() -> f64 {
+    10.0
+}
+
+fn default_group_commands_per_second() -> f64 {
+    1.0
+}
+
+fn default_max_concurrent_commands() -> usize {
+    1
+}
+
+fn default_max_queued_commands() -> usize {
+    100
+}
+
+fn default_addr() -> String {
+    AUTO_ADDR.to_string()
+}
+
+fn default_button_gesture_window_ms() -> u64 {
+    400
+}
+
+fn default_button_hold_ms() -> u64 {
+    800
+}
+
 #[derive(Clone, Deserialize, Debug)]
 pub struct HueSettings {
+    /// Address of the Hue bridge, or "auto" to find it through mDNS
+    #[serde(default = "default_addr")]
     pub addr: String,
+
+    /// Created by running `hue-mqtt pair`
+    #[serde(default)]
     pub appkey: String,
+
+    /// Only needed for the Hue Entertainment API, which is not used by
+    /// hue-mqtt. Stored by `hue-mqtt pair` for use by other tools.
+    pub clientkey: Option<String>,
+
     pub self_signed_cert: Option<String>,
     pub disable_host_name_verification: Option<bool>,
     pub eventsource_timeout_seconds: u64,
+
+    #[serde(default = "default_command_timeout_ms")]
+    pub command_timeout_ms: u64,
+
+    #[serde(default = "default_command_retries")]
+    pub command_retries: u32,
+
+    #[serde(default = "default_commands_per_second")]
+    pub commands_per_second: f64,
+
+    #[serde(default = "default_group_commands_per_second")]
+    pub group_commands_per_second: f64,
+
+    #[serde(default = "default_max_concurrent_commands")]
+    pub max_concurrent_commands: usize,
+
+    #[serde(default = "default_max_queued_commands")]
+    pub max_queued_commands: usize,
+
+    #[serde(default = "default_button_gesture_window_ms")]
+    pub button_gesture_window_ms: u64,
+
+    #[serde(default = "default_button_hold_ms")]
+    pub button_hold_ms: u64,
+
+    /// Record all traffic with the Hue bridge to this JSON lines file
+    pub capture_file: Option<String>,
+}
+
+fn default_legacy_sensor_value() -> bool {
+    true
+}
+
+fn default_status_topic() -> String {
+    "home/hue-mqtt/status".to_string()
+}
+
+fn default_device_availability_topic() -> String {
+    "home/devices/hue/{id}/availability".to_string()
+}
+
+fn default_device_battery_topic() -> String {
+    "home/devices/hue/{id}/battery".to_string()
+}
+
+fn default_button_event_topic() -> String {
+    "home/sensors/hue/{id}/event".to_string()
+}
+
+fn default_button_gesture_topic() -> String {
+    "home/sensors/hue/{id}/gesture".to_string()
+}
+
+fn default_rotary_topic() -> String {
+    "home/sensors/hue/{id}/rotary".to_string()
+}
+
+fn default_light_topic_error() -> String {
+    "home/lights/hue/{id}/error".to_string()
+}
+
+fn default_group_topic() -> String {
+    "home/groups/hue/{id}".to_string()
+}
+
+fn default_group_topic_set() -> String {
+    "home/groups/hue/{id}/set".to_string()
+}
+
+fn default_scene_topic() -> String {
+    "home/scenes/hue/{id}".to_string()
+}
+
+fn default_scene_topic_recall() -> String {
+    "home/scenes/hue/{id}/recall".to_string()
+}
+
+#[derive(Clone, Copy, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MqttTransport {
+    #[default]
+    Tcp,
+    Tls,
+    Ws,
+    Wss,
 }
 
 #[derive(Clone, Deserialize, Debug)]
@@ -14,15 +148,90 @@ pub struct MqttSettings {
     pub id: String,
     pub host: String,
     pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+
+    #[serde(default)]
+    pub transport: MqttTransport,
+    pub ws_path: Option<String>,
+    pub ca_cert: Option<String>,
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+
     pub sensor_topic: String,
+
+    /// Publish sensor values as strings, as done by earlier versions
+    #[serde(default = "default_legacy_sensor_value")]
+    pub legacy_sensor_value: bool,
+
+    /// Also publish light colors as HSV, RGB and hex
+    #[serde(default)]
+    pub publish_color_spaces: bool,
+
+    #[serde(default = "default_button_event_topic")]
+    pub button_event_topic: String,
+
+    #[serde(default = "default_button_gesture_topic")]
+    pub button_gesture_topic: String,
+
+    #[serde(default = "default_rotary_topic")]
+    pub rotary_topic: String,
+
     pub light_topic: String,
     pub light_topic_set: String,
+
+    #[serde(default = "default_light_topic_error")]
+    pub light_topic_error: String,
+
+    #[serde(default = "default_group_topic")]
+    pub group_topic: String,
+
+    #[serde(default = "default_group_topic_set")]
+    pub group_topic_set: String,
+
+    #[serde(default = "default_scene_topic")]
+    pub scene_topic: String,
+
+    #[serde(default = "default_scene_topic_recall")]
+    pub scene_topic_recall: String,
+
+    #[serde(default = "default_status_topic")]
+    pub status_topic: String,
+
+    #[serde(default = "default_device_availability_topic")]
+    pub device_availability_topic: String,
+
+    #[serde(default = "default_device_battery_topic")]
+    pub device_battery_topic: String,
+}
+
+fn default_discovery_prefix() -> String {
+    "homeassistant".to_string()
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct HomeAssistantSettings {
+    #[serde(default = "default_discovery_prefix")]
+    pub discovery_prefix: String,
+}
+
+fn default_http_addr() -> String {
+    "0.0.0.0:9464".to_string()
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct HttpSettings {
+    /// Address and port to listen on
+    #[serde(default = "default_http_addr")]
+    pub addr: String,
 }
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct Settings {
     pub hue_bridge: HueSettings,
     pub mqtt: MqttSettings,
+    pub homeassistant: Option<HomeAssistantSettings>,
+    pub http: Option<HttpSettings>,
 }
 
 pub fn read_settings() -> Result<Settings, config::ConfigError> {
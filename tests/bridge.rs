@@ -0,0 +1,157 @@
+//! Runs hue-mqtt against a mock Hue bridge and MQTT broker
+
+mod support;
+
+use serde_json::json;
+use support::{TestEnv, DEVICE_ID, LIGHT_ID};
+
+const LIGHT_TOPIC: &str = "home/lights/hue/light-1";
+const LIGHT_TOPIC_SET: &str = "home/lights/hue/light-1/set";
+
+#[tokio::test]
+async fn publishes_initial_light_state() {
+    let env = TestEnv::start().await;
+
+    let publish = env
+        .broker
+        .wait_for_publish(LIGHT_TOPIC, |publish| publish.retain)
+        .await;
+    let light = publish.json();
+
+    assert_eq!(light["id"], LIGHT_ID);
+    assert_eq!(light["name"], "Living room lamp");
+    assert_eq!(light["power"], true);
+    assert_eq!(light["brightness"], 0.5);
+}
+
+#[tokio::test]
+async fn publishes_eventsource_updates() {
+    let env = TestEnv::start().await;
+
+    env.bridge.send_events(json!([{
+        "type": "update",
+        "id": "event-1",
+        "creationtime": "2024-01-01T00:00:00Z",
+        "data": [{
+            "id": LIGHT_ID,
+            "type": "light",
+            "owner": { "rid": DEVICE_ID, "rtype": "device" },
+            "dimming": { "brightness": 20.0 }
+        }]
+    }]));
+
+    let publish = env
+        .broker
+        .wait_for_publish(LIGHT_TOPIC, |publish| {
+            publish.json()["brightness"].as_f64() == Some(0.2)
+        })
+        .await;
+
+    assert_eq!(publish.json()["power"], true);
+}
+
+#[tokio::test]
+async fn sends_set_messages_to_bridge() {
+    let env = TestEnv::start().await;
+
+    env.broker.publish(
+        LIGHT_TOPIC_SET,
+        json!({
+            "id": LIGHT_ID,
+            "name": "Living room lamp",
+            "power": true,
+            "brightness": 0.8,
+            "color": { "ct": 2700 },
+            "transition_ms": 400
+        }),
+    );
+
+    let body = env.bridge.wait_for_put("light", LIGHT_ID).await;
+
+    assert_eq!(body["on"], json!({ "on": true }));
+    assert_eq!(body["dimming"], json!({ "brightness": 80.0 }));
+    assert_eq!(body["dynamics"], json!({ "duration": 400 }));
+
+    let mirek = body["color_temperature"]["mirek"].as_f64().unwrap();
+    assert!((mirek - 370.37).abs() < 0.01);
+}
+
+#[tokio::test]
+async fn sends_relative_changes_to_bridge() {
+    let env = TestEnv::start().await;
+
+    env.broker.publish(
+        LIGHT_TOPIC_SET,
+        json!({
+            "id": LIGHT_ID,
+            "name": "Living room lamp",
+            "brightness_step": -0.25,
+            "ct_step": 50
+        }),
+    );
+
+    let body = env.bridge.wait_for_put("light", LIGHT_ID).await;
+
+    assert_eq!(
+        body["dimming_delta"],
+        json!({ "action": "down", "brightness_delta": 25.0 })
+    );
+    assert_eq!(
+        body["color_temperature_delta"],
+        json!({ "action": "up", "mirek_delta": 50 })
+    );
+}
+
+#[tokio::test]
+async fn merges_queued_set_messages() {
+    let env = TestEnv::start_with(
+        |_| {},
+        |settings| settings.hue_bridge.commands_per_second = 1.0,
+    )
+    .await;
+
+    let set = |payload: serde_json::Value| {
+        let mut message = json!({ "id": LIGHT_ID, "name": "Living room lamp" });
+        message
+            .as_object_mut()
+            .unwrap()
+            .extend(payload.as_object().unwrap().clone());
+
+        env.broker.publish(LIGHT_TOPIC_SET, message);
+    };
+
+    // The first command uses up the rate limit, so the next two are queued
+    set(json!({ "brightness": 0.1 }));
+    env.bridge.wait_for_put("light", LIGHT_ID).await;
+
+    set(json!({ "power": false }));
+    set(json!({ "brightness": 0.3 }));
+
+    support::wait_until("second PUT", || env.bridge.puts().len() == 2).await;
+    env.bridge.settle().await;
+
+    let puts = env.bridge.puts();
+    assert_eq!(puts.len(), 2);
+    assert_eq!(puts[1].body["on"], json!({ "on": false }));
+    let brightness = puts[1].body["dimming"]["brightness"].as_f64().unwrap();
+    assert!((brightness - 30.0).abs() < 0.01);
+}
+
+#[tokio::test]
+async fn projects_colors_onto_light_gamut() {
+    let env = TestEnv::start().await;
+
+    env.broker.publish(
+        LIGHT_TOPIC_SET,
+        json!({
+            "id": LIGHT_ID,
+            "name": "Living room lamp",
+            "color": { "x": 0.8, "y": 0.2 }
+        }),
+    );
+
+    let body = env.bridge.wait_for_put("light", LIGHT_ID).await;
+    let x = body["color"]["xy"]["x"].as_f64().unwrap();
+
+    assert!(x <= 0.6915 + 0.001, "x = {x} is outside of gamut C");
+}
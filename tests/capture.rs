@@ -0,0 +1,110 @@
+//! Records traffic with a mock Hue bridge using the `capture_file` setting.
+//! Kept in its own test binary, as capturing can only be started once per
+//! process.
+
+mod support;
+
+use std::path::Path;
+
+use serde_json::{json, Value};
+use support::{mock_bridge::APPKEY, replay::replay, wait_until, TestEnv, DEVICE_ID, LIGHT_ID};
+
+fn read_capture(path: &Path) -> Vec<Value> {
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn captures_bridge_traffic() {
+    let capture_file =
+        std::env::temp_dir().join(format!("hue-mqtt-capture-{}.jsonl", std::process::id()));
+    // Left over from an earlier run, should be overwritten
+    std::fs::write(&capture_file, "stale\n").unwrap();
+
+    let env = TestEnv::start_with(
+        |_| {},
+        |settings| {
+            settings.hue_bridge.capture_file = Some(capture_file.to_string_lossy().to_string())
+        },
+    )
+    .await;
+
+    env.bridge.send_events(json!([{
+        "type": "update",
+        "id": "event-1",
+        "creationtime": "2024-01-01T00:00:00Z",
+        "data": [{
+            "id": LIGHT_ID,
+            "type": "light",
+            "owner": { "rid": DEVICE_ID, "rtype": "device" },
+            "dimming": { "brightness": 20.0 }
+        }]
+    }]));
+
+    // Anything echoing the app key back, such as the bridge's whitelist,
+    // must not end up in the capture
+    env.bridge.send_events(json!([{
+        "type": "update",
+        "id": "event-2",
+        "creationtime": "2024-01-01T00:00:01Z",
+        "data": [{
+            "id": "whitelist-1",
+            "type": "whitelist",
+            "appkey": APPKEY
+        }]
+    }]));
+
+    env.broker.publish(
+        "home/lights/hue/light-1/set",
+        json!({ "id": LIGHT_ID, "name": "Living room lamp", "power": false }),
+    );
+
+    let has_kind = |kind: &str| {
+        read_capture(&capture_file)
+            .iter()
+            .any(|entry| entry["kind"] == kind)
+    };
+
+    let has_redacted_sse = || {
+        read_capture(&capture_file).iter().any(|entry| {
+            entry["kind"] == "sse" && entry["data"].as_str().unwrap().contains("<redacted>")
+        })
+    };
+
+    wait_until("captured SSE", has_redacted_sse).await;
+    wait_until("captured PUT", || has_kind("put")).await;
+
+    let entries = read_capture(&capture_file);
+
+    assert!(entries
+        .iter()
+        .all(|entry| entry["timestamp"].as_u64().unwrap() > 0));
+
+    let light_get = entries
+        .iter()
+        .find(|entry| entry["kind"] == "get" && entry["path"] == "/clip/v2/resource/light")
+        .unwrap();
+    assert_eq!(light_get["status"], 200);
+    assert_eq!(light_get["body"]["data"][0]["id"], LIGHT_ID);
+
+    let sse = entries.iter().find(|entry| entry["kind"] == "sse").unwrap();
+    let events: Value = serde_json::from_str(sse["data"].as_str().unwrap()).unwrap();
+    assert_eq!(events[0]["data"][0]["dimming"]["brightness"], 20.0);
+
+    let put = entries.iter().find(|entry| entry["kind"] == "put").unwrap();
+    assert_eq!(put["path"], format!("/clip/v2/resource/light/{LIGHT_ID}"));
+    assert_eq!(put["request"]["on"], json!({ "on": false }));
+    assert_eq!(put["status"], 200);
+
+    let contents = std::fs::read_to_string(&capture_file).unwrap();
+    assert!(!contents.contains(APPKEY));
+
+    // Captures can be replayed as fixtures
+    let output = replay(&capture_file).await;
+    assert!(output.contains(r#""brightness":0.2"#));
+
+    let _ = std::fs::remove_file(&capture_file);
+}
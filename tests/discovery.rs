@@ -0,0 +1,47 @@
+//! Connects to a bridge by its ID, as done after finding it through mDNS. Kept
+//! in its own test binary, as the discovered bridge is global state.
+
+mod support;
+
+use hue_mqtt::{
+    hue::{
+        discovery::{set_discovered_bridge, DiscoveredBridge},
+        rest::light::get_hue_lights,
+    },
+    protocols::https::mk_hyper_https_client,
+};
+use support::{light, mk_settings, mock_bridge::MockBridge, mock_broker::MockBroker, LIGHT_ID};
+
+const BRIDGE_ID: &str = "001788fffe41dde0";
+
+#[tokio::test]
+async fn verifies_certificate_against_bridge_id() {
+    let bridge = MockBridge::start_with_bridge_id(BRIDGE_ID).await;
+    let broker = MockBroker::start().await;
+    bridge.set_resources("light", vec![light(LIGHT_ID, "device-1", true, 50.0)]);
+
+    let mut settings = mk_settings(&bridge, &broker);
+    settings.hue_bridge.addr = format!("{BRIDGE_ID}:{}", bridge.addr.port());
+
+    set_discovered_bridge(DiscoveredBridge {
+        id: BRIDGE_ID.to_string(),
+        addr: bridge.addr,
+    });
+
+    let https_client = mk_hyper_https_client(&settings.hue_bridge).unwrap();
+    let lights = get_hue_lights(&settings, &https_client).await.unwrap();
+    assert_eq!(lights[0].id, LIGHT_ID);
+
+    // A different bridge answering at the same address is rejected
+    let other_id = "001788fffe000000";
+    settings.hue_bridge.addr = format!("{other_id}:{}", bridge.addr.port());
+
+    set_discovered_bridge(DiscoveredBridge {
+        id: other_id.to_string(),
+        addr: bridge.addr,
+    });
+
+    let https_client = mk_hyper_https_client(&settings.hue_bridge).unwrap();
+    let result = get_hue_lights(&settings, &https_client).await;
+    assert!(result.is_err());
+}
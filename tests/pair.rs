@@ -0,0 +1,44 @@
+//! Runs `hue-mqtt pair` against a mock Hue bridge
+
+mod support;
+
+use hue_mqtt::pair::pair;
+use support::mock_bridge::{MockBridge, APPKEY, CLIENTKEY};
+
+#[tokio::test]
+async fn writes_appkey_after_link_button_press() {
+    let bridge = MockBridge::start().await;
+    bridge.press_link_button_after(1);
+
+    let settings_file =
+        std::env::temp_dir().join(format!("hue-mqtt-pair-{}.toml", std::process::id()));
+
+    std::fs::write(
+        &settings_file,
+        format!(
+            r#"# Hue bridge settings
+[hue_bridge]
+addr = "localhost:{port}"
+self_signed_cert = """{ca_cert}"""
+eventsource_timeout_seconds = 300
+
+[mqtt]
+id = "hue-mqtt"
+"#,
+            port = bridge.addr.port(),
+            ca_cert = bridge.ca_cert,
+        ),
+    )
+    .unwrap();
+
+    pair(&settings_file).await.unwrap();
+
+    let contents = std::fs::read_to_string(&settings_file).unwrap();
+    let _ = std::fs::remove_file(&settings_file);
+
+    assert_eq!(bridge.pair_requests(), 2);
+    assert!(contents.starts_with("# Hue bridge settings\n[hue_bridge]\n"));
+    assert!(contents.contains(&format!("appkey = \"{APPKEY}\"")));
+    assert!(contents.contains(&format!("clientkey = \"{CLIENTKEY}\"")));
+    assert!(contents.contains("[mqtt]\nid = \"hue-mqtt\""));
+}
@@ -0,0 +1,20 @@
+//! Replays recorded Hue bridge traffic and compares the published MQTT
+//! messages against golden files, see `support/replay.rs`
+
+mod support;
+
+use support::replay::assert_replay_matches_golden;
+
+/// Four presses in quick succession, where the bridge batches the
+/// eventsource events as `[true], [false, true, false], [true, false],
+/// [true, false]`
+#[tokio::test]
+async fn rapid_presses() {
+    assert_replay_matches_golden("rapid_presses").await;
+}
+
+/// A press and release that only shows up when polling the button resource
+#[tokio::test]
+async fn missed_transition() {
+    assert_replay_matches_golden("missed_transition").await;
+}
@@ -0,0 +1,305 @@
+//! In-process fake of the Hue bridge CLIP v2 API
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use hyper::{server::conn::Http, service::service_fn, Body, Method, Request, Response, StatusCode};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa};
+use serde_json::{json, Value};
+use tokio::{net::TcpListener, sync::broadcast};
+use tokio_rustls::{
+    rustls::{self, ServerConfig},
+    TlsAcceptor,
+};
+
+use super::wait_until;
+
+pub const APPKEY: &str = "test-appkey";
+pub const CLIENTKEY: &str = "0123456789ABCDEF0123456789ABCDEF";
+
+/// A PUT request received by the bridge
+#[derive(Clone, Debug)]
+pub struct RecordedPut {
+    pub rtype: String,
+    pub id: String,
+    pub body: Value,
+}
+
+#[derive(Default)]
+struct BridgeState {
+    resources: HashMap<String, Vec<Value>>,
+    puts: Vec<RecordedPut>,
+    eventstream_clients: usize,
+
+    /// Number of pairing requests to reject before the link button counts as
+    /// pressed
+    link_button_pressed_after: usize,
+    pair_requests: usize,
+}
+
+#[derive(Clone)]
+pub struct MockBridge {
+    pub addr: SocketAddr,
+
+    /// PEM encoded CA certificate that signed the bridge's certificate, to be
+    /// used as `self_signed_cert`
+    pub ca_cert: String,
+
+    state: Arc<Mutex<BridgeState>>,
+    events: broadcast::Sender<String>,
+}
+
+/// Generates a CA certificate, and a certificate signed by it. Like real Hue
+/// bridges, the certificate has the bridge ID as its common name and no
+/// subject alternative names if `bridge_id` is given, otherwise it is for
+/// "localhost".
+fn mk_certs(bridge_id: Option<&str>) -> (String, ServerConfig) {
+    let mut ca_params = CertificateParams::new(vec![]);
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = Certificate::from_params(ca_params).unwrap();
+
+    let cert_params = match bridge_id {
+        Some(bridge_id) => {
+            let mut cert_params = CertificateParams::new(vec![]);
+            cert_params
+                .distinguished_name
+                .push(DnType::CommonName, bridge_id);
+            cert_params
+        }
+        None => CertificateParams::new(vec!["localhost".to_string()]),
+    };
+    let cert = Certificate::from_params(cert_params).unwrap();
+
+    let server_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![rustls::Certificate(
+                cert.serialize_der_with_signer(&ca).unwrap(),
+            )],
+            rustls::PrivateKey(cert.serialize_private_key_der()),
+        )
+        .unwrap();
+
+    (ca.serialize_pem().unwrap(), server_config)
+}
+
+fn json_response(status: StatusCode, body: Value) -> Response<Body> {
+    let mut response = Response::new(Body::from(body.to_string()));
+    *response.status_mut() = status;
+
+    response
+}
+
+impl MockBridge {
+    pub async fn start() -> MockBridge {
+        Self::start_with_certs(mk_certs(None)).await
+    }
+
+    /// Starts a bridge whose certificate is only valid for the given bridge ID
+    pub async fn start_with_bridge_id(bridge_id: &str) -> MockBridge {
+        Self::start_with_certs(mk_certs(Some(bridge_id))).await
+    }
+
+    async fn start_with_certs((ca_cert, server_config): (String, ServerConfig)) -> MockBridge {
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (events, _) = broadcast::channel(100);
+
+        let bridge = MockBridge {
+            addr: listener.local_addr().unwrap(),
+            ca_cert,
+            state: Default::default(),
+            events,
+        };
+
+        {
+            let bridge = bridge.clone();
+
+            tokio::spawn(async move {
+                loop {
+                    let Ok((stream, _)) = listener.accept().await else {
+                        continue;
+                    };
+
+                    let acceptor = acceptor.clone();
+                    let bridge = bridge.clone();
+
+                    tokio::spawn(async move {
+                        let Ok(stream) = acceptor.accept(stream).await else {
+                            return;
+                        };
+
+                        let service = service_fn(move |req| {
+                            let bridge = bridge.clone();
+                            async move { Ok::<_, Infallible>(bridge.handle_request(req).await) }
+                        });
+
+                        let _ = Http::new().serve_connection(stream, service).await;
+                    });
+                }
+            });
+        }
+
+        bridge
+    }
+
+    async fn handle_request(&self, req: Request<Body>) -> Response<Body> {
+        if req.method() == Method::POST && req.uri().path() == "/api" {
+            return self.pair(req).await;
+        }
+
+        let appkey = req.headers().get("hue-application-key");
+
+        if appkey.map(|appkey| appkey != APPKEY).unwrap_or(true) {
+            return json_response(
+                StatusCode::FORBIDDEN,
+                json!({ "errors": [{ "description": "unauthorized user" }], "data": [] }),
+            );
+        }
+
+        let method = req.method().clone();
+        let path = req.uri().path().to_string();
+        let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+
+        match (method, segments.as_slice()) {
+            (Method::GET, ["eventstream", "clip", "v2"]) => self.eventstream(),
+            (Method::GET, ["clip", "v2", "resource", rtype]) => {
+                let state = self.state.lock().unwrap();
+                let data = state.resources.get(*rtype).cloned().unwrap_or_default();
+
+                json_response(StatusCode::OK, json!({ "errors": [], "data": data }))
+            }
+            (Method::PUT, ["clip", "v2", "resource", rtype, id]) => {
+                let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                let body: Value = serde_json::from_slice(&body).unwrap();
+
+                self.state.lock().unwrap().puts.push(RecordedPut {
+                    rtype: rtype.to_string(),
+                    id: id.to_string(),
+                    body,
+                });
+
+                json_response(
+                    StatusCode::OK,
+                    json!({ "errors": [], "data": [{ "rid": id, "rtype": rtype }] }),
+                )
+            }
+            _ => json_response(
+                StatusCode::NOT_FOUND,
+                json!({ "errors": [{ "description": "resource not found" }], "data": [] }),
+            ),
+        }
+    }
+
+    /// Handles a request for a new app key, as in the Hue API v1
+    async fn pair(&self, req: Request<Body>) -> Response<Body> {
+        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["generateclientkey"], true);
+
+        let mut state = self.state.lock().unwrap();
+        state.pair_requests += 1;
+
+        let response = if state.pair_requests > state.link_button_pressed_after {
+            json!([{ "success": { "username": APPKEY, "clientkey": CLIENTKEY } }])
+        } else {
+            json!([{
+                "error": { "type": 101, "address": "", "description": "link button not pressed" }
+            }])
+        };
+
+        json_response(StatusCode::OK, response)
+    }
+
+    fn eventstream(&self) -> Response<Body> {
+        let (mut sender, body) = Body::channel();
+        let mut events = self.events.subscribe();
+        let state = self.state.clone();
+
+        state.lock().unwrap().eventstream_clients += 1;
+
+        tokio::spawn(async move {
+            let _ = sender.send_data(": hi\n\n".into()).await;
+            let mut event_id = 0;
+
+            while let Ok(data) = events.recv().await {
+                event_id += 1;
+                let message = format!("id: {event_id}:0\ndata: {data}\n\n");
+
+                if sender.send_data(message.into()).await.is_err() {
+                    break;
+                }
+            }
+
+            state.lock().unwrap().eventstream_clients -= 1;
+        });
+
+        let mut response = Response::new(body);
+        response
+            .headers_mut()
+            .insert("content-type", "text/event-stream".parse().unwrap());
+
+        response
+    }
+
+    /// Replaces all resources of the given type returned by GET requests
+    pub fn set_resources(&self, rtype: &str, resources: Vec<Value>) {
+        let mut state = self.state.lock().unwrap();
+        state.resources.insert(rtype.to_string(), resources);
+    }
+
+    /// Rejects the given number of pairing requests before handing out an app
+    /// key, as if the link button was pressed in the meantime
+    pub fn press_link_button_after(&self, pair_requests: usize) {
+        self.state.lock().unwrap().link_button_pressed_after = pair_requests;
+    }
+
+    /// Returns the number of pairing requests received so far
+    pub fn pair_requests(&self) -> usize {
+        self.state.lock().unwrap().pair_requests
+    }
+
+    /// Sends a batch of events to all clients connected to the eventstream
+    pub fn send_events(&self, events: Value) {
+        self.events.send(events.to_string()).unwrap();
+    }
+
+    /// Waits until a client has connected to the eventstream
+    pub async fn wait_for_eventstream(&self) {
+        wait_until("eventstream client", || {
+            self.state.lock().unwrap().eventstream_clients > 0
+        })
+        .await;
+    }
+
+    /// Returns all PUT requests received so far
+    pub fn puts(&self) -> Vec<RecordedPut> {
+        self.state.lock().unwrap().puts.clone()
+    }
+
+    /// Waits for a PUT request to the given resource, and returns its body
+    pub async fn wait_for_put(&self, rtype: &str, id: &str) -> Value {
+        let find = || {
+            self.puts()
+                .into_iter()
+                .find(|put| put.rtype == rtype && put.id == id)
+                .map(|put| put.body)
+        };
+
+        wait_until(&format!("PUT to {rtype}/{id}"), || find().is_some()).await;
+
+        find().unwrap()
+    }
+
+    /// Waits long enough for any queued commands to have been sent
+    pub async fn settle(&self) {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+    }
+}
@@ -0,0 +1,256 @@
+//! Minimal in-process MQTT 3.1.1 broker, supporting just enough of the protocol
+//! for hue-mqtt to connect, subscribe and publish
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use bytes::BytesMut;
+use rumqttc::{
+    ConnAck, ConnectReturnCode, Packet, PingResp, PubAck, Publish, QoS, SubAck, SubscribeReasonCode,
+};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+
+use super::wait_until;
+
+/// A message published by a client
+#[derive(Clone, Debug)]
+pub struct RecordedPublish {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub retain: bool,
+}
+
+impl RecordedPublish {
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.payload).unwrap()
+    }
+}
+
+struct Client {
+    filters: Vec<String>,
+    sender: mpsc::UnboundedSender<Publish>,
+}
+
+#[derive(Default)]
+struct BrokerState {
+    published: Vec<RecordedPublish>,
+    clients: Vec<Client>,
+}
+
+#[derive(Clone)]
+pub struct MockBroker {
+    pub addr: SocketAddr,
+    state: Arc<Mutex<BrokerState>>,
+}
+
+fn write_packet(packet: Packet, buffer: &mut BytesMut) {
+    match packet {
+        Packet::ConnAck(connack) => connack.write(buffer),
+        Packet::SubAck(suback) => suback.write(buffer),
+        Packet::PubAck(puback) => puback.write(buffer),
+        Packet::Publish(publish) => publish.write(buffer),
+        Packet::PingResp => PingResp.write(buffer),
+        packet => panic!("Unsupported outgoing packet {packet:?}"),
+    }
+    .unwrap();
+}
+
+impl MockBroker {
+    pub async fn start() -> MockBroker {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        let broker = MockBroker {
+            addr: listener.local_addr().unwrap(),
+            state: Default::default(),
+        };
+
+        {
+            let broker = broker.clone();
+
+            tokio::spawn(async move {
+                loop {
+                    let Ok((stream, _)) = listener.accept().await else {
+                        continue;
+                    };
+
+                    let broker = broker.clone();
+                    tokio::spawn(async move { broker.handle_connection(stream).await });
+                }
+            });
+        }
+
+        broker
+    }
+
+    async fn handle_connection(&self, stream: TcpStream) {
+        let (mut reader, mut writer) = stream.into_split();
+        let (packet_sender, mut packet_receiver) = mpsc::unbounded_channel::<Packet>();
+        let (publish_sender, mut publish_receiver) = mpsc::unbounded_channel::<Publish>();
+
+        tokio::spawn(async move {
+            loop {
+                let packet = tokio::select! {
+                    Some(packet) = packet_receiver.recv() => packet,
+                    Some(publish) = publish_receiver.recv() => Packet::Publish(publish),
+                    else => break,
+                };
+
+                let mut buffer = BytesMut::new();
+                write_packet(packet, &mut buffer);
+
+                if writer.write_all(&buffer).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let client_index = {
+            let mut state = self.state.lock().unwrap();
+            state.clients.push(Client {
+                filters: vec![],
+                sender: publish_sender,
+            });
+            state.clients.len() - 1
+        };
+
+        let mut buffer = BytesMut::new();
+
+        loop {
+            let packet = match rumqttc::read(&mut buffer, 1024 * 1024) {
+                Ok(packet) => packet,
+                Err(rumqttc::Error::InsufficientBytes(_)) => {
+                    match reader.read_buf(&mut buffer).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(_) => continue,
+                    }
+                }
+                Err(e) => panic!("Error decoding MQTT packet: {e:?}"),
+            };
+
+            let response = match packet {
+                Packet::Connect(_) => Some(Packet::ConnAck(ConnAck::new(
+                    ConnectReturnCode::Success,
+                    false,
+                ))),
+                Packet::Subscribe(subscribe) => {
+                    let mut state = self.state.lock().unwrap();
+                    let client = &mut state.clients[client_index];
+
+                    client
+                        .filters
+                        .extend(subscribe.filters.iter().map(|filter| filter.path.clone()));
+
+                    let return_codes = subscribe
+                        .filters
+                        .iter()
+                        .map(|filter| SubscribeReasonCode::Success(filter.qos))
+                        .collect();
+
+                    Some(Packet::SubAck(SubAck::new(subscribe.pkid, return_codes)))
+                }
+                Packet::Publish(publish) => {
+                    let pkid = publish.pkid;
+                    let qos = publish.qos;
+                    self.route(publish);
+
+                    (qos == QoS::AtLeastOnce).then(|| Packet::PubAck(PubAck::new(pkid)))
+                }
+                Packet::PingReq => Some(Packet::PingResp),
+                Packet::Disconnect => break,
+                _ => None,
+            };
+
+            if let Some(response) = response {
+                if packet_sender.send(response).is_err() {
+                    break;
+                }
+            }
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.clients[client_index].filters.clear();
+    }
+
+    /// Records a message and delivers it to matching subscribers
+    fn route(&self, publish: Publish) {
+        let mut state = self.state.lock().unwrap();
+
+        state.published.push(RecordedPublish {
+            topic: publish.topic.clone(),
+            payload: publish.payload.to_vec(),
+            retain: publish.retain,
+        });
+
+        for client in &state.clients {
+            if client
+                .filters
+                .iter()
+                .any(|filter| rumqttc::matches(&publish.topic, filter))
+            {
+                let mut publish =
+                    Publish::new(&publish.topic, QoS::AtMostOnce, publish.payload.to_vec());
+                publish.pkid = 0;
+
+                let _ = client.sender.send(publish);
+            }
+        }
+    }
+
+    /// Publishes a message to all clients subscribed to the topic, as if it
+    /// was sent by another MQTT client
+    pub fn publish(&self, topic: &str, payload: Value) {
+        self.route(Publish::new(topic, QoS::AtMostOnce, payload.to_string()));
+    }
+
+    /// Returns all messages published to the topic so far
+    pub fn published(&self, topic: &str) -> Vec<RecordedPublish> {
+        let state = self.state.lock().unwrap();
+
+        state
+            .published
+            .iter()
+            .filter(|publish| publish.topic == topic)
+            .cloned()
+            .collect()
+    }
+
+    /// Waits until a client has subscribed to the topic
+    pub async fn wait_for_subscription(&self, topic: &str) {
+        wait_until(&format!("subscription to {topic}"), || {
+            let state = self.state.lock().unwrap();
+
+            state.clients.iter().any(|client| {
+                client
+                    .filters
+                    .iter()
+                    .any(|filter| rumqttc::matches(topic, filter))
+            })
+        })
+        .await;
+    }
+
+    /// Waits for a message on the topic that satisfies `f`, and returns it
+    pub async fn wait_for_publish(
+        &self,
+        topic: &str,
+        f: impl Fn(&RecordedPublish) -> bool,
+    ) -> RecordedPublish {
+        let find = || {
+            self.published(topic)
+                .into_iter()
+                .rev()
+                .find(|publish| f(publish))
+        };
+
+        wait_until(&format!("publish to {topic}"), || find().is_some()).await;
+
+        find().unwrap()
+    }
+}
@@ -0,0 +1,149 @@
+#![allow(dead_code)]
+
+pub mod mock_bridge;
+pub mod mock_broker;
+pub mod replay;
+
+use std::time::Duration;
+
+use hue_mqtt::settings::Settings;
+use serde_json::{json, Value};
+
+use self::{
+    mock_bridge::{MockBridge, APPKEY},
+    mock_broker::MockBroker,
+};
+
+/// Polls `f` until it returns true, panicking after a few seconds
+pub async fn wait_until(what: &str, f: impl Fn() -> bool) {
+    let started = tokio::time::Instant::now();
+
+    while !f() {
+        if started.elapsed() > Duration::from_secs(5) {
+            panic!("Timed out waiting for {what}");
+        }
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+/// Settings pointing hue-mqtt at the mock bridge and broker
+pub fn mk_settings(bridge: &MockBridge, broker: &MockBroker) -> Settings {
+    let toml = format!(
+        r#"
+        [hue_bridge]
+        addr = "localhost:{bridge_port}"
+        appkey = "{APPKEY}"
+        self_signed_cert = """{ca_cert}"""
+        eventsource_timeout_seconds = 300
+
+        [mqtt]
+        id = "hue-mqtt-test"
+        host = "127.0.0.1"
+        port = {broker_port}
+        light_topic = "home/lights/hue/{{id}}"
+        light_topic_set = "home/lights/hue/{{id}}/set"
+        sensor_topic = "home/sensors/hue/{{id}}"
+        legacy_sensor_value = false
+        "#,
+        bridge_port = bridge.addr.port(),
+        ca_cert = bridge.ca_cert,
+        broker_port = broker.addr.port(),
+    );
+
+    config::Config::builder()
+        .add_source(config::File::from_str(&toml, config::FileFormat::Toml))
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap()
+}
+
+pub fn device(id: &str, name: &str, services: &[(&str, &str)]) -> Value {
+    let services: Vec<Value> = services
+        .iter()
+        .map(|(rtype, rid)| json!({ "rid": rid, "rtype": rtype }))
+        .collect();
+
+    json!({
+        "id": id,
+        "id_v1": null,
+        "product_data": {
+            "model_id": "LCT015",
+            "manufacturer_name": "Signify Netherlands B.V.",
+            "product_name": "Hue color lamp"
+        },
+        "metadata": { "name": name, "archetype": "sultan_bulb" },
+        "services": services
+    })
+}
+
+pub fn light(id: &str, owner: &str, on: bool, brightness: f32) -> Value {
+    json!({
+        "id": id,
+        "id_v1": "/lights/1",
+        "owner": { "rid": owner, "rtype": "device" },
+        "metadata": { "name": "Lamp", "archetype": "sultan_bulb" },
+        "on": { "on": on },
+        "dimming": { "brightness": brightness },
+        "color": {
+            "xy": { "x": 0.3, "y": 0.3 },
+            "gamut": {
+                "red": { "x": 0.6915, "y": 0.3083 },
+                "green": { "x": 0.17, "y": 0.7 },
+                "blue": { "x": 0.1532, "y": 0.0475 }
+            },
+            "gamut_type": "C"
+        },
+        "color_temperature": {
+            "mirek": null,
+            "mirek_schema": { "mirek_minimum": 153, "mirek_maximum": 500 }
+        }
+    })
+}
+
+/// A mock bridge with a single color light, and hue-mqtt running against it
+pub struct TestEnv {
+    pub bridge: MockBridge,
+    pub broker: MockBroker,
+}
+
+pub const DEVICE_ID: &str = "device-1";
+pub const LIGHT_ID: &str = "light-1";
+
+impl TestEnv {
+    pub async fn start() -> TestEnv {
+        Self::start_with(|_| {}, |_| {}).await
+    }
+
+    /// Starts hue-mqtt after adding resources to the bridge with `setup` and
+    /// adjusting its settings with `configure`
+    pub async fn start_with(
+        setup: impl FnOnce(&MockBridge),
+        configure: impl FnOnce(&mut Settings),
+    ) -> TestEnv {
+        let bridge = MockBridge::start().await;
+        let broker = MockBroker::start().await;
+
+        bridge.set_resources(
+            "device",
+            vec![device(
+                DEVICE_ID,
+                "Living room lamp",
+                &[("light", LIGHT_ID)],
+            )],
+        );
+        bridge.set_resources("light", vec![light(LIGHT_ID, DEVICE_ID, true, 50.0)]);
+        setup(&bridge);
+
+        let mut settings = mk_settings(&bridge, &broker);
+        configure(&mut settings);
+
+        hue_mqtt::start(&settings).await.unwrap();
+
+        bridge.wait_for_eventstream().await;
+        broker.wait_for_subscription("home/lights/hue/x/set").await;
+
+        TestEnv { bridge, broker }
+    }
+}
@@ -0,0 +1,223 @@
+//! Replays recorded Hue bridge traffic against the eventsource and button
+//! polling handlers, and compares the resulting MQTT messages against a golden
+//! file.
+//!
+//! Fixtures live in `tests/fixtures/replay/<name>.jsonl`, with one entry per
+//! line:
+//!
+//! - `{"t": 120, "kind": "sse", "data": "[...]"}`: the raw `data` string of an
+//!   eventsource event
+//! - `{"t": 0, "kind": "get", "path": "/clip/v2/resource/button", "body": {...}}`:
+//!   the response body of a GET request
+//! - `{"t": 300, "kind": "put", ...}`: a PUT request, which is ignored
+//!
+//! `t` is the number of milliseconds since the start of the recording. GET
+//! responses before the first eventsource event make up the initial bridge
+//! state. Later responses for `/clip/v2/resource/button` are handled as
+//! button polls, other entries are ignored.
+//!
+//! Files recorded with the `hue_bridge.capture_file` setting can be used as
+//! fixtures as is.
+//!
+//! Set `UPDATE_GOLDEN=1` to overwrite the golden files with the current
+//! output.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use hue_mqtt::{
+    health::Health,
+    hue::{
+        events::{handle_eventsource_event, EventsourceState},
+        pending_commands::PendingCommands,
+        polling::handle_hue_button_poll,
+        rest::{button::ButtonData, get_hue_state},
+    },
+    mqtt::command_queue::CommandQueue,
+    protocols::{https::mk_hyper_https_client, mqtt::MqttClient},
+};
+use rumqttc::{AsyncClient, Request};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::time::Instant;
+
+use super::{mk_settings, mock_bridge::MockBridge, mock_broker::MockBroker};
+
+/// How long to keep running after the last entry, so that pending button
+/// gesture timers get to fire
+const SETTLE_TIME: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum Entry {
+    Sse { t: u64, data: String },
+    Get { t: u64, path: String, body: Value },
+    Put { t: u64 },
+}
+
+impl Entry {
+    fn t(&self) -> Duration {
+        match self {
+            Entry::Sse { t, .. } | Entry::Get { t, .. } | Entry::Put { t } => {
+                Duration::from_millis(*t)
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ButtonResponse {
+    data: Vec<ButtonData>,
+}
+
+fn fixture_path(name: &str, extension: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/replay")
+        .join(format!("{name}.{extension}"))
+}
+
+fn read_fixture(path: &Path) -> Vec<Entry> {
+    let contents = fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {e}", path.display()));
+
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .unwrap_or_else(|e| panic!("Invalid entry on line {}: {e}", i + 1))
+        })
+        .collect()
+}
+
+/// Formats a published message as a line of the golden file
+fn format_publish(t: Duration, topic: &str, retain: bool, payload: &[u8]) -> String {
+    let retained = if retain { " (retained)" } else { "" };
+    let payload = String::from_utf8_lossy(payload);
+
+    format!("[{:>6} ms] {topic}{retained} {payload}\n", t.as_millis())
+}
+
+/// Replays the fixture at `path` and returns the MQTT messages published in
+/// response, formatted as in the golden file
+pub async fn replay(path: &Path) -> String {
+    let entries = read_fixture(path);
+    let first_sse = entries
+        .iter()
+        .position(|entry| matches!(entry, Entry::Sse { .. }))
+        .unwrap_or(entries.len());
+    let (initial, entries) = entries.split_at(first_sse);
+
+    // Serve the initial state from the mock bridge, so that it gets fetched
+    // and cleaned up the same way as at startup
+    let bridge = MockBridge::start().await;
+    let broker = MockBroker::start().await;
+
+    for entry in initial {
+        if let Entry::Get { path, body, .. } = entry {
+            let rtype = path.trim_start_matches("/clip/v2/resource/");
+            let data = body["data"].as_array().cloned().unwrap_or_default();
+            bridge.set_resources(rtype, data);
+        }
+    }
+
+    let settings = mk_settings(&bridge, &broker);
+    let https_client = mk_hyper_https_client(&settings.hue_bridge).unwrap();
+    let init_state = get_hue_state(&settings, &https_client).await.unwrap();
+
+    // Publishes end up in this channel instead of going to a broker
+    let (sender, receiver) = flume::unbounded();
+    let mqtt_client = MqttClient {
+        client: AsyncClient::from_senders(sender),
+        command_queue: CommandQueue::new(&settings),
+        connected: Default::default(),
+    };
+
+    let health = Health::new(&mqtt_client);
+    let state = EventsourceState::new(
+        &settings,
+        &mqtt_client,
+        &init_state,
+        &PendingCommands::default(),
+        &health,
+    );
+
+    // From here on, time only advances when all tasks are idle, which makes
+    // the replay deterministic regardless of how long handling takes
+    tokio::time::pause();
+    let start = Instant::now();
+
+    let output = Arc::new(Mutex::new(vec![]));
+
+    {
+        let output = output.clone();
+
+        tokio::spawn(async move {
+            while let Ok(request) = receiver.recv_async().await {
+                if let Request::Publish(publish) = request {
+                    output.lock().unwrap().push(format_publish(
+                        start.elapsed(),
+                        &publish.topic,
+                        publish.retain,
+                        &publish.payload,
+                    ));
+                }
+            }
+        });
+    }
+
+    for entry in entries {
+        tokio::time::sleep_until(start + entry.t()).await;
+
+        match entry {
+            Entry::Sse { data, .. } => {
+                handle_eventsource_event(&settings, &mqtt_client, &state, data.clone())
+                    .await
+                    .unwrap();
+            }
+            Entry::Get { path, body, .. } if path == "/clip/v2/resource/button" => {
+                let response: ButtonResponse = serde_json::from_value(body.clone()).unwrap();
+
+                handle_hue_button_poll(&settings, &mqtt_client, &state, response.data)
+                    .await
+                    .unwrap();
+            }
+            Entry::Get { .. } | Entry::Put { .. } => {}
+        }
+    }
+
+    tokio::time::sleep(SETTLE_TIME).await;
+
+    let output = output.lock().unwrap();
+    output.concat()
+}
+
+/// Replays the named fixture and asserts that the output matches its golden
+/// file
+pub async fn assert_replay_matches_golden(name: &str) {
+    let output = replay(&fixture_path(name, "jsonl")).await;
+    let golden_path = fixture_path(name, "golden");
+
+    if std::env::var("UPDATE_GOLDEN").is_ok() {
+        fs::write(&golden_path, &output).unwrap();
+        return;
+    }
+
+    let golden = fs::read_to_string(&golden_path).unwrap_or_else(|e| {
+        panic!(
+            "Failed to read {}: {e}, run with UPDATE_GOLDEN=1 to create it",
+            golden_path.display()
+        )
+    });
+
+    assert!(
+        output == golden,
+        "Output of replaying {name} differs from {}\n\nExpected:\n{golden}\nGot:\n{output}",
+        golden_path.display()
+    );
+}